@@ -0,0 +1,316 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::disasm::{self, Item, Line};
+use super::{op_info, Instruction, Mode, Value, OP_HLT, OP_JF, OP_JT};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edge {
+    // Falling through to the next instruction, including a jump not taken.
+    Next(usize),
+    Taken(usize),
+    // A jump whose target is only known at run time.
+    Indirect,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<Line>,
+    pub successors: Vec<Edge>,
+}
+
+impl Block {
+    // One past the last word of the block.
+    pub fn end(&self) -> usize {
+        self.instructions.last().map(|line| line.address + line.raw.len()).unwrap_or(self.start)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Analysis {
+    pub blocks: BTreeMap<usize, Block>,
+    // Every word of every reachable instruction.
+    pub code: BTreeSet<usize>,
+    // Reachable instructions that write to code through a fixed address, as
+    // (pc, address).
+    pub self_modifying: Vec<(usize, usize)>,
+    // Instructions the linear sweep finds that can't be reached from pc 0.
+    pub unreachable: Vec<usize>,
+    // Reachable addresses that don't hold a valid instruction.
+    pub invalid: Vec<usize>,
+    // Jumps with targets only known at run time. Code reached only through
+    // these shows up as unreachable.
+    pub indirect: Vec<usize>,
+}
+
+impl Analysis {
+    pub fn is_code(&self, addr: usize) -> bool {
+        self.code.contains(&addr)
+    }
+
+    // The CFG as a Graphviz digraph, one node per block.
+    pub fn dot(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph intcode {{");
+        let _ = writeln!(out, "    node [shape=box, fontname=\"monospace\"];");
+        for block in self.blocks.values() {
+            let label: String = block.instructions
+                .iter()
+                .map(|line| format!("{}: {}\\l", line.address, escape(&line.source())))
+                .collect();
+            let _ = writeln!(out, "    b{} [label=\"{}\"];", block.start, label);
+        }
+        if !self.indirect.is_empty() {
+            let _ = writeln!(out, "    indirect [label=\"?\", shape=circle];");
+        }
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let _ = match edge {
+                    Edge::Next(to) => writeln!(out, "    b{} -> b{};", block.start, to),
+                    Edge::Taken(to) => writeln!(out, "    b{} -> b{} [label=\"taken\"];", block.start, to),
+                    Edge::Indirect => writeln!(out, "    b{} -> indirect [style=dashed];", block.start),
+                };
+            }
+        }
+        let _ = writeln!(out, "}}");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Where control can go after the instruction in `line`.
+fn successors(line: &Line) -> Vec<Edge> {
+    let next = line.address + line.raw.len();
+    let inst = match Instruction::decode(line.address, line.raw[0]) {
+        Ok(inst) => inst,
+        Err(_) => return Vec::new(),
+    };
+    let (cond, target) = match &line.item {
+        Item::Instruction { operands, .. } if inst.op == OP_JT || inst.op == OP_JF => (operands[0], operands[1]),
+        _ if inst.op == OP_HLT => return Vec::new(),
+        _ => return vec![Edge::Next(next)],
+    };
+
+    let taken = match (target.mode, target.value) {
+        (Mode::Immediate, t) if t >= 0 => Some(Edge::Taken(t as usize)),
+        // a negative target is an error, so there's nowhere to go
+        (Mode::Immediate, _) => None,
+        _ => Some(Edge::Indirect),
+    };
+    // an immediate condition decides the jump now
+    match (cond.mode, (cond.value != 0) == (inst.op == OP_JT)) {
+        (Mode::Immediate, true) => taken.into_iter().collect(),
+        (Mode::Immediate, false) => vec![Edge::Next(next)],
+        _ => taken.into_iter().chain(Some(Edge::Next(next))).collect(),
+    }
+}
+
+// Recover the control-flow graph of `memory` starting at pc 0.
+pub fn analyse(memory: &[Value]) -> Analysis {
+    let mut analysis = Analysis::default();
+
+    // find everything reachable first
+    let mut reachable: BTreeMap<usize, (Line, Vec<Edge>)> = BTreeMap::new();
+    let mut invalid = BTreeSet::new();
+    let mut pending = vec![0];
+    while let Some(pc) = pending.pop() {
+        if reachable.contains_key(&pc) || invalid.contains(&pc) {
+            continue;
+        }
+        let line = match pc < memory.len() {
+            true => disasm::decode_at(memory, pc),
+            false => None,
+        };
+        let line = match line {
+            Some(line) => line,
+            None => {
+                invalid.insert(pc);
+                continue;
+            }
+        };
+        let edges = successors(&line);
+        for edge in &edges {
+            match edge {
+                Edge::Next(to) | Edge::Taken(to) => pending.push(*to),
+                Edge::Indirect => analysis.indirect.push(pc),
+            }
+        }
+        analysis.code.extend(pc..pc + line.raw.len());
+        reachable.insert(pc, (line, edges));
+    }
+    analysis.invalid = invalid.into_iter().collect();
+    analysis.indirect.sort_unstable();
+
+    // blocks start at the entry point, at jump targets and after jumps
+    let mut leaders = BTreeSet::new();
+    if reachable.contains_key(&0) {
+        leaders.insert(0);
+    }
+    for (_, edges) in reachable.values() {
+        let branches = !matches!(edges.as_slice(), [Edge::Next(_)]);
+        for edge in edges {
+            match edge {
+                Edge::Taken(to) => {
+                    leaders.insert(*to);
+                }
+                Edge::Next(to) if branches => {
+                    leaders.insert(*to);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    for leader in leaders.iter().filter(|pc| reachable.contains_key(pc)) {
+        let mut block = Block { start: *leader, instructions: Vec::new(), successors: Vec::new() };
+        let mut pc = *leader;
+        loop {
+            let (line, edges) = &reachable[&pc];
+            block.instructions.push(line.clone());
+            match edges.as_slice() {
+                [Edge::Next(next)] if reachable.contains_key(next) && !leaders.contains(next) => pc = *next,
+                _ => {
+                    block.successors = edges
+                        .iter()
+                        .filter(|edge| match edge {
+                            Edge::Next(to) | Edge::Taken(to) => reachable.contains_key(to),
+                            Edge::Indirect => true,
+                        })
+                        .cloned()
+                        .collect();
+                    break;
+                }
+            }
+        }
+        analysis.blocks.insert(*leader, block);
+    }
+
+    for (pc, (line, _)) in &reachable {
+        if let Item::Instruction { operands, .. } = &line.item {
+            let written = match op_writes(line) {
+                true => operands.last(),
+                false => None,
+            };
+            if let Some(dest) = written.filter(|dest| dest.mode == Mode::Position && dest.value >= 0) {
+                if analysis.is_code(dest.value as usize) {
+                    analysis.self_modifying.push((*pc, dest.value as usize));
+                }
+            }
+        }
+    }
+
+    for line in disasm::disassemble(memory) {
+        let overlaps = (line.address..line.address + line.raw.len()).any(|addr| analysis.is_code(addr));
+        if let Item::Instruction { .. } = line.item {
+            if !overlaps {
+                analysis.unreachable.push(line.address);
+            }
+        }
+    }
+
+    analysis
+}
+
+fn op_writes(line: &Line) -> bool {
+    Instruction::decode(line.address, line.raw[0])
+        .ok()
+        .and_then(|inst| op_info(inst.op))
+        .map(|info| info.writes)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    mod analysis {
+        use super::super::*;
+        use super::super::super::Program;
+
+        const COMPARE: [Value; 47] = [
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125,
+            20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99,
+        ];
+
+        #[test]
+        fn straight_line() {
+            let analysis = analyse(&[1, 0, 0, 0, 2, 0, 0, 0, 99]);
+            assert_eq!(1, analysis.blocks.len());
+            let block = &analysis.blocks[&0];
+            assert_eq!(3, block.instructions.len());
+            assert_eq!(9, block.end());
+            assert!(block.successors.is_empty());
+            assert_eq!(vec![(0, 0), (4, 0)], analysis.self_modifying);
+            assert!(analysis.unreachable.is_empty());
+        }
+
+        #[test]
+        fn branches() {
+            let analysis = analyse(&COMPARE);
+            let starts: Vec<usize> = analysis.blocks.keys().cloned().collect();
+            assert_eq!(vec![0, 9, 16, 22, 31, 36, 46], starts);
+            assert_eq!(vec![Edge::Taken(22), Edge::Next(9)], analysis.blocks[&0].successors);
+            assert_eq!(vec![Edge::Taken(36)], analysis.blocks[&16].successors);
+            assert_eq!(vec![Edge::Taken(46)], analysis.blocks[&22].successors);
+            assert!(analysis.blocks[&46].successors.is_empty());
+            // 19 to 21 and 45 are data
+            assert!(analysis.is_code(18) && !analysis.is_code(19) && !analysis.is_code(21) && analysis.is_code(22));
+            assert!(!analysis.is_code(45));
+            assert!(analysis.self_modifying.is_empty());
+            assert!(analysis.indirect.is_empty());
+            assert!(analysis.invalid.is_empty());
+        }
+
+        #[test]
+        fn unreachable_and_invalid() {
+            // the add at 3 is jumped over, and the jump at 8 lands on data
+            let analysis = analyse(&[1105, 1, 7, 1, 0, 0, 0, 1105, 1, 12, 99, 99, 42]);
+            assert_eq!(vec![3, 10, 11], analysis.unreachable);
+            assert_eq!(vec![12], analysis.invalid);
+            assert_eq!(vec![Edge::Taken(7)], analysis.blocks[&0].successors);
+            assert!(analysis.blocks[&7].successors.is_empty());
+
+            // never taken, then jumping off the end
+            let analysis = analyse(&[1105, 0, 9, 1106, 0, 9, 99, 0, 0]);
+            assert_eq!(vec![9], analysis.invalid);
+            assert_eq!(vec![6], analysis.unreachable);
+            assert_eq!(1, analysis.blocks.len());
+            assert_eq!(2, analysis.blocks[&0].instructions.len());
+        }
+
+        #[test]
+        fn indirect_jumps() {
+            let analysis = analyse(&[1005, 3, 7, 2106, 0, 0, 99, 99]);
+            assert_eq!(vec![3], analysis.indirect);
+            assert_eq!(vec![Edge::Indirect], analysis.blocks[&3].successors);
+            assert_eq!(vec![Edge::Taken(7), Edge::Next(3)], analysis.blocks[&0].successors);
+            assert_eq!(vec![6], analysis.unreachable);
+        }
+
+        #[test]
+        fn day2() {
+            let program = Program::load_from_file("data/input.txt").unwrap();
+            let analysis = analyse(&program.memory().to_vec());
+            assert_eq!(1, analysis.blocks.len());
+            // the first instruction is patched with the noun and verb and its
+            // destination is reused
+            assert!(analysis.self_modifying.contains(&(4, 3)));
+            assert!(analysis.self_modifying.contains(&(136, 0)));
+            assert!(analysis.is_code(140) && !analysis.is_code(141));
+        }
+
+        #[test]
+        fn dot() {
+            let dot = analyse(&[1005, 3, 7, 2106, 0, 0, 99, 99]).dot();
+            assert!(dot.starts_with("digraph intcode {\n"));
+            assert!(dot.contains("    b0 [label=\"0: JT [3], 7\\l\"];\n"));
+            assert!(dot.contains("    b0 -> b7 [label=\"taken\"];\n"));
+            assert!(dot.contains("    b0 -> b3;\n"));
+            assert!(dot.contains("    b3 -> indirect [style=dashed];\n"));
+            assert!(dot.ends_with("}\n"));
+            assert_eq!("a \\\"b\\\" \\\\", escape("a \"b\" \\"));
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+use super::{Error, Program, Status, Value};
+
+const NEWLINE: Value = 10;
+
+// Talks to a program that speaks ASCII: lines of text go in as character
+// codes ending in a newline, and output is collected into lines. Anything
+// outside the ASCII range (usually a final answer) is kept separately.
+pub struct Ascii {
+    program: Program,
+    partial: String,
+    lines: VecDeque<String>,
+    values: Vec<Value>,
+}
+
+impl Ascii {
+    pub fn new(program: Program) -> Ascii {
+        Ascii {
+            program,
+            partial: String::new(),
+            lines: VecDeque::new(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut Program {
+        &mut self.program
+    }
+
+    // Queue a line of input. The newline is added. A line with anything
+    // outside ASCII is rejected and nothing from it is queued.
+    pub fn send_line(&mut self, line: &str) -> Result<(), Error> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if let Some(c) = line.chars().find(|c| !c.is_ascii()) {
+            return Err(Error::NotAscii(c));
+        }
+        for c in line.chars() {
+            self.program.push_input(c as Value);
+        }
+        self.program.push_input(NEWLINE);
+        Ok(())
+    }
+
+    // Queue several lines, e.g. a springscript or movement routine. Stops at
+    // the first line that isn't ASCII.
+    pub fn send_lines(&mut self, lines: &[&str]) -> Result<(), Error> {
+        for line in lines {
+            self.send_line(line)?;
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<Status, Error> {
+        let status = self.program.run();
+        for value in self.program.take_output() {
+            match value {
+                NEWLINE => self.lines.push_back(self.partial.split_off(0)),
+                v if (0..128).contains(&v) => self.partial.push(v as u8 as char),
+                v => self.values.push(v),
+            }
+        }
+        status
+    }
+
+    // Complete lines of output since the last call.
+    pub fn read_lines(&mut self) -> Vec<String> {
+        self.lines.drain(..).collect()
+    }
+
+    // Output after the last newline, usually a prompt.
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    // Non-ASCII output values since the last call.
+    pub fn take_values(&mut self) -> Vec<Value> {
+        self.values.split_off(0)
+    }
+
+    // Run the program against a terminal: everything it prints goes to
+    // `output`, and whenever it waits for input a line is read from `input`.
+    // Returns when the program halts or `input` runs out. If the program
+    // fails, whatever it printed first is still written out.
+    pub fn interactive<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> Result<Status, Error> {
+        let io_error = |e: std::io::Error| Error::Io(e.to_string());
+        loop {
+            let result = self.run();
+            let finished = result != Ok(Status::WaitingForInput);
+            let mut lines = self.read_lines();
+            if finished && !self.partial.is_empty() {
+                lines.push(self.partial.split_off(0));
+            }
+            for line in lines {
+                writeln!(output, "{}", line).map_err(io_error)?;
+            }
+            for value in self.take_values() {
+                writeln!(output, "[{}]", value).map_err(io_error)?;
+            }
+            if finished {
+                output.flush().map_err(io_error)?;
+                return result;
+            }
+
+            write!(output, "{}", self.partial.split_off(0)).map_err(io_error)?;
+            output.flush().map_err(io_error)?;
+            let mut line = String::new();
+            match input.read_line(&mut line).map_err(io_error)? {
+                0 => return result,
+                _ => self.send_line(&line)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod ascii {
+        use super::super::*;
+        use super::super::super::asm;
+
+        // Prints a prompt, then echoes each line back upper-cased until it
+        // reads an empty line, then outputs 1000 plus how many lines it saw.
+        const SHOUT: &str = "
+            start:  OUT 62
+                    OUT 32
+                    ADD 0, 0, [len]
+            read:   IN [c]
+                    EQ [c], 10, [tmp]
+                    JT [tmp], eol
+                    ADD [len], 1, [len]
+                    LT [c], 97, [tmp]
+                    JT [tmp], emit
+                    ADD [c], -32, [c]
+            emit:   OUT [c]
+                    JT 1, read
+            eol:    JF [len], done
+                    OUT 10
+                    ADD [count], 1, [count]
+                    JT 1, start
+            done:   ADD [count], 1000, [big]
+                    OUT [big]
+                    HLT
+            c:      .data 0
+            tmp:    .data 0
+            len:    .data 0
+            count:  .data 0
+            big:    .data 0
+        ";
+
+        fn shout() -> Ascii {
+            Ascii::new(Program::load(asm::assemble(SHOUT).unwrap().code))
+        }
+
+        #[test]
+        fn lines_and_prompt() {
+            let mut a = shout();
+            assert_eq!(Ok(Status::WaitingForInput), a.run());
+            assert_eq!(Vec::<String>::new(), a.read_lines());
+            assert_eq!("> ", a.partial());
+
+            a.send_lines(&["hello", "Big World\n"]).unwrap();
+            assert_eq!(Ok(Status::WaitingForInput), a.run());
+            assert_eq!(vec![String::from("> HELLO"), String::from("> BIG WORLD")], a.read_lines());
+            assert_eq!("> ", a.partial());
+        }
+
+        #[test]
+        fn values_are_separate() {
+            let mut a = shout();
+            a.send_lines(&["a", ""]).unwrap();
+            assert_eq!(Ok(Status::Halted), a.run());
+            assert_eq!(vec![String::from("> A")], a.read_lines());
+            assert_eq!("> ", a.partial());
+            assert_eq!(vec![1001], a.take_values());
+            assert_eq!(Vec::<Value>::new(), a.take_values());
+        }
+
+        #[test]
+        fn interactive() {
+            let mut a = shout();
+            let mut out = Vec::new();
+            assert_eq!(Ok(Status::Halted), a.interactive(&b"one\ntwo\n\n"[..], &mut out));
+            assert_eq!("> ONE\n> TWO\n> [1002]\n", String::from_utf8(out).unwrap());
+        }
+
+        #[test]
+        fn interactive_input_ends() {
+            let mut a = shout();
+            let mut out = Vec::new();
+            assert_eq!(Ok(Status::WaitingForInput), a.interactive(&b"x\n"[..], &mut out));
+            assert_eq!("> X\n> ", String::from_utf8(out).unwrap());
+        }
+
+        #[test]
+        fn not_ascii() {
+            let mut a = shout();
+            assert_eq!(Err(Error::NotAscii('é')), a.send_line("café"));
+            assert_eq!(Err(Error::NotAscii('é')), a.send_lines(&["ok", "é"]));
+            assert_eq!(Ok(Status::WaitingForInput), a.run());
+            assert_eq!(vec![String::from("> OK")], a.read_lines());
+
+            let mut out = Vec::new();
+            assert_eq!(Err(Error::NotAscii('é')), a.interactive(&b"x\n\xc3\xa9\n"[..], &mut out));
+            assert_eq!("> X\n> ", String::from_utf8(out).unwrap());
+        }
+
+        #[test]
+        fn interactive_error_keeps_output() {
+            // prints a line and a value, then hits a bad opcode
+            let mut a = Ascii::new(Program::load(vec![104, 104, 104, 105, 104, 10, 104, 33, 104, 999, 42]));
+            let mut out = Vec::new();
+            assert_eq!(
+                Err(Error::UnknownOpcode { pc: 10, instruction: 42 }),
+                a.interactive(&b""[..], &mut out),
+            );
+            assert_eq!("hi\n!\n[999]\n", String::from_utf8(out).unwrap());
+        }
+    }
+}
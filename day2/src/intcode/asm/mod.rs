@@ -0,0 +1,494 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+use super::image::MAX_MEMORY_HINT;
+use super::{op_by_mnemonic, Mode, OpInfo, Value};
+
+// Assembles the textual form printed by the disassembler back into intcode.
+//
+//     ; comments run to the end of the line
+//     .const LIMIT 10
+//     start:  IN [count]
+//             LT [count], LIMIT, [rb+1]
+//             JT [rb+1], start
+//             HLT
+//     count:  .data 0
+//
+// Operands are immediate (`5`, `label`), position (`[5]`, `[label+1]`) or
+// relative (`[rb]`, `[rb-2]`). `.data` emits values as-is and `.zero N`
+// reserves N cells.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ErrorKind {
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    OperandCount { expected: usize, found: usize },
+    BadOperand(String),
+    BadExpression(String),
+    BadLabel(String),
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    RecursiveConstant(String),
+    ImmediateDestination,
+    AddressOverflow,
+    TooLong,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Error {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic {}", m),
+            ErrorKind::UnknownDirective(d) => write!(f, "unknown directive {}", d),
+            ErrorKind::OperandCount { expected, found } =>
+                write!(f, "expected {} operands, found {}", expected, found),
+            ErrorKind::BadOperand(o) => write!(f, "bad operand {:?}", o),
+            ErrorKind::BadExpression(e) => write!(f, "bad expression {:?}", e),
+            ErrorKind::BadLabel(l) => write!(f, "bad label {:?}", l),
+            ErrorKind::DuplicateSymbol(s) => write!(f, "symbol {} defined more than once", s),
+            ErrorKind::UndefinedSymbol(s) => write!(f, "undefined symbol {}", s),
+            ErrorKind::RecursiveConstant(s) => write!(f, "constant {} refers to itself", s),
+            ErrorKind::ImmediateDestination => write!(f, "destination can't be immediate"),
+            ErrorKind::AddressOverflow => write!(f, "arithmetic overflows"),
+            ErrorKind::TooLong => write!(f, "program is longer than {} cells", MAX_MEMORY_HINT),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Assembly {
+    pub code: Vec<Value>,
+    // Label addresses, useful for setting breakpoints or symbol tables.
+    pub labels: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(Value),
+    Symbol(String),
+}
+
+// A sum of signed terms, resolved once every label has an address.
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(bool, Term)>,
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    mode: Mode,
+    expr: Expr,
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction { info: OpInfo, operands: Vec<Operand> },
+    Data(Vec<Expr>),
+    Zero(Expr),
+}
+
+#[derive(Debug)]
+enum Symbol {
+    Label(Value),
+    Const(Expr, usize),
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn parse_expr(text: &str) -> Result<Expr, ErrorKind> {
+    let bad = || ErrorKind::BadExpression(text.to_string());
+
+    let mut terms = Vec::new();
+    let mut rest = text.trim();
+    let mut negative = false;
+    loop {
+        // any number of leading signs
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '-' => negative = !negative,
+                '+' => (),
+                _ => break,
+            }
+            rest = rest[1..].trim_start();
+        }
+
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let token = rest[..end].trim();
+        // numbers take their sign with them, as the magnitude of Value::MIN
+        // doesn't fit on its own
+        let number = token.parse::<i128>().map(|n| if negative { -n } else { n });
+        let term = match number.map(Value::try_from) {
+            Ok(Ok(value)) => (false, Term::Number(value)),
+            Err(_) if is_identifier(token) => (negative, Term::Symbol(token.to_string())),
+            _ => return Err(bad()),
+        };
+        terms.push(term);
+        negative = false;
+
+        rest = &rest[end..];
+        if rest.is_empty() {
+            break;
+        }
+        // the sign starting the next term is consumed at the top of the loop
+        if rest[1..].trim().is_empty() {
+            return Err(bad());
+        }
+    }
+
+    Ok(Expr { terms })
+}
+
+fn parse_operand(text: &str) -> Result<Operand, ErrorKind> {
+    let text = text.trim();
+    if !text.starts_with('[') {
+        return Ok(Operand { mode: Mode::Immediate, expr: parse_expr(text)? });
+    }
+    if !text.ends_with(']') {
+        return Err(ErrorKind::BadOperand(text.to_string()));
+    }
+
+    let inner = text[1..text.len() - 1].trim();
+    let relative = inner.get(..2).is_some_and(|prefix| prefix.eq_ignore_ascii_case("rb")) &&
+        !inner[2..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+    match relative {
+        true => match inner[2..].trim() {
+            "" => Ok(Operand { mode: Mode::Relative, expr: Expr { terms: vec![(false, Term::Number(0))] } }),
+            offset if offset.starts_with('+') || offset.starts_with('-') =>
+                Ok(Operand { mode: Mode::Relative, expr: parse_expr(offset)? }),
+            _ => Err(ErrorKind::BadOperand(text.to_string())),
+        }
+        false => Ok(Operand { mode: Mode::Position, expr: parse_expr(inner)? }),
+    }
+}
+
+fn split_list(text: &str) -> Vec<&str> {
+    match text.trim() {
+        "" => Vec::new(),
+        text => text.split(',').map(|item| item.trim()).collect(),
+    }
+}
+
+struct Assembler {
+    symbols: BTreeMap<String, Symbol>,
+}
+
+impl Assembler {
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), ErrorKind> {
+        if !is_identifier(name) || name.eq_ignore_ascii_case("rb") {
+            return Err(ErrorKind::BadLabel(name.to_string()));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(ErrorKind::DuplicateSymbol(name.to_string()));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, depth: usize) -> Result<Value, ErrorKind> {
+        let mut total: Value = 0;
+        for (negative, term) in &expr.terms {
+            let value = match term {
+                Term::Number(n) => *n,
+                Term::Symbol(name) => match self.symbols.get(name) {
+                    Some(Symbol::Label(address)) => *address,
+                    Some(Symbol::Const(_, _)) if depth > self.symbols.len() =>
+                        return Err(ErrorKind::RecursiveConstant(name.clone())),
+                    Some(Symbol::Const(expr, _)) => self.eval(expr, depth + 1)?,
+                    None => return Err(ErrorKind::UndefinedSymbol(name.clone())),
+                }
+            };
+            total = match negative {
+                true => total.checked_sub(value),
+                false => total.checked_add(value),
+            }.ok_or(ErrorKind::AddressOverflow)?;
+        }
+        Ok(total)
+    }
+
+    // First pass: strip comments, record labels and constants, and work out
+    // where each statement will be placed.
+    fn parse(&mut self, source: &str) -> Result<Vec<(usize, Statement)>, Error> {
+        let mut statements = Vec::new();
+        let mut address: Value = 0;
+
+        for (idx, raw_line) in source.lines().enumerate() {
+            let line = idx + 1;
+            let err = |kind| Error { line, kind };
+            // programs are kept to the size an image can hold, so a big
+            // `.zero` is an error rather than running out of memory
+            let advance = |address: Value, size: Value| match address.checked_add(size) {
+                Some(next) if next as u64 <= MAX_MEMORY_HINT as u64 => Ok(next),
+                _ => Err(err(ErrorKind::TooLong)),
+            };
+
+            let mut text = match raw_line.find(';') {
+                Some(pos) => &raw_line[..pos],
+                None => raw_line,
+            }.trim();
+
+            while let Some(pos) = text.find(':') {
+                let label = text[..pos].trim();
+                self.define(label, Symbol::Label(address)).map_err(err)?;
+                text = text[pos + 1..].trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = match text.find(char::is_whitespace) {
+                Some(pos) => (&text[..pos], text[pos..].trim()),
+                None => (text, ""),
+            };
+
+            let statement = match word {
+                ".const" => {
+                    let (name, value) = match rest.find(char::is_whitespace) {
+                        Some(pos) => (&rest[..pos], rest[pos..].trim()),
+                        None => return Err(err(ErrorKind::BadExpression(rest.to_string()))),
+                    };
+                    let expr = parse_expr(value).map_err(err)?;
+                    self.define(name, Symbol::Const(expr, line)).map_err(err)?;
+                    continue;
+                }
+                ".data" => {
+                    let values = split_list(rest)
+                        .into_iter()
+                        .map(parse_expr)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(err)?;
+                    address = advance(address, values.len() as Value)?;
+                    Statement::Data(values)
+                }
+                ".zero" => {
+                    // the size has to be known during this pass, so only
+                    // constants defined above can be used
+                    let expr = parse_expr(rest).map_err(err)?;
+                    address = advance(address, self.eval(&expr, 0).map_err(err)?.max(0))?;
+                    Statement::Zero(expr)
+                }
+                directive if directive.starts_with('.') =>
+                    return Err(err(ErrorKind::UnknownDirective(directive.to_string()))),
+                mnemonic => {
+                    let info = match op_by_mnemonic(mnemonic) {
+                        Some(info) => info,
+                        None => return Err(err(ErrorKind::UnknownMnemonic(mnemonic.to_string()))),
+                    };
+                    let operands = split_list(rest)
+                        .into_iter()
+                        .map(parse_operand)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(err)?;
+                    if operands.len() != info.params {
+                        return Err(err(ErrorKind::OperandCount { expected: info.params, found: operands.len() }));
+                    }
+                    if info.writes && operands[info.params - 1].mode == Mode::Immediate {
+                        return Err(err(ErrorKind::ImmediateDestination));
+                    }
+                    address = advance(address, 1 + info.params as Value)?;
+                    Statement::Instruction { info, operands }
+                }
+            };
+            statements.push((line, statement));
+        }
+
+        Ok(statements)
+    }
+
+    // Second pass: every label is known, so expressions can be evaluated.
+    fn emit(&self, statements: &[(usize, Statement)]) -> Result<Vec<Value>, Error> {
+        let mut code = Vec::new();
+        for (line, statement) in statements {
+            let err = |kind| Error { line: *line, kind };
+            match statement {
+                Statement::Instruction { info, operands } => {
+                    let mut raw = info.op;
+                    let mut scale = 100;
+                    for operand in operands {
+                        raw += scale * match operand.mode {
+                            Mode::Position => 0,
+                            Mode::Immediate => 1,
+                            Mode::Relative => 2,
+                        };
+                        scale *= 10;
+                    }
+                    code.push(raw);
+                    for operand in operands {
+                        code.push(self.eval(&operand.expr, 0).map_err(err)?);
+                    }
+                }
+                Statement::Data(values) => {
+                    for value in values {
+                        code.push(self.eval(value, 0).map_err(err)?);
+                    }
+                }
+                Statement::Zero(size) => {
+                    let size = self.eval(size, 0).map_err(err)?.max(0);
+                    code.extend((0..size).map(|_| 0));
+                }
+            }
+        }
+        Ok(code)
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, Error> {
+    let mut assembler = Assembler { symbols: BTreeMap::new() };
+    let statements = assembler.parse(source)?;
+
+    // constants are checked even if nothing refers to them
+    for symbol in assembler.symbols.values() {
+        if let Symbol::Const(expr, line) = symbol {
+            assembler.eval(expr, 0).map_err(|kind| Error { line: *line, kind })?;
+        }
+    }
+
+    let code = assembler.emit(&statements)?;
+    let labels = assembler.symbols
+        .iter()
+        .filter_map(|(name, symbol)| match symbol {
+            Symbol::Label(address) => Some((name.clone(), *address)),
+            Symbol::Const(_, _) => None,
+        })
+        .collect();
+
+    Ok(Assembly { code, labels })
+}
+
+#[cfg(test)]
+mod tests {
+    mod asm {
+        use super::super::*;
+        use super::super::super::disasm;
+
+        fn code(source: &str) -> Vec<Value> {
+            assemble(source).unwrap().code
+        }
+
+        fn error(source: &str) -> Error {
+            assemble(source).unwrap_err()
+        }
+
+        #[test]
+        fn instructions() {
+            assert_eq!(vec![1, 0, 0, 0, 99], code("ADD [0], [0], [0]\nHLT"));
+            assert_eq!(vec![1002, 4, 3, 4, 33], code("mul [4], 3, [4]\n.data 33"));
+            assert_eq!(vec![204, -1, 209, 0, 109, 3], code("OUT [rb-1]\nARB [rb]\nARB 3"));
+        }
+
+        #[test]
+        fn comments_and_blank_lines() {
+            assert_eq!(vec![99], code("; nothing\n\n   HLT ; stop\n"));
+        }
+
+        #[test]
+        fn labels() {
+            let assembly = assemble("start: IN [value]\n  JT 1, start\nvalue:\n  .data 0").unwrap();
+            assert_eq!(vec![3, 5, 1105, 1, 0, 0], assembly.code);
+            assert_eq!(Some(&0), assembly.labels.get("start"));
+            assert_eq!(Some(&5), assembly.labels.get("value"));
+        }
+
+        #[test]
+        fn constants_and_expressions() {
+            let source = "
+                .const BASE 10
+                .const NEXT BASE + 1
+                ADD [table+1], NEXT, [rb-BASE]
+                table: .data -BASE, NEXT - 2
+            ";
+            assert_eq!(vec![21001, 5, 11, -10, -10, 9], code(source));
+        }
+
+        #[test]
+        fn zero() {
+            assert_eq!(vec![99, 0, 0, 0, 7], code(".const N 3\nHLT\n.zero N\nend: .data end+3"));
+        }
+
+        #[test]
+        fn errors() {
+            assert_eq!(Error { line: 2, kind: ErrorKind::UnknownMnemonic(String::from("NOP")) }, error("HLT\nNOP"));
+            assert_eq!(Error { line: 1, kind: ErrorKind::UnknownDirective(String::from(".word")) }, error(".word 1"));
+            assert_eq!(
+                Error { line: 1, kind: ErrorKind::OperandCount { expected: 3, found: 2 } },
+                error("ADD 1, 2"),
+            );
+            assert_eq!(Error { line: 1, kind: ErrorKind::ImmediateDestination }, error("IN 4"));
+            assert_eq!(
+                Error { line: 3, kind: ErrorKind::UndefinedSymbol(String::from("missing")) },
+                error("HLT\n\nJT 1, missing"),
+            );
+            assert_eq!(
+                Error { line: 2, kind: ErrorKind::DuplicateSymbol(String::from("a")) },
+                error("a: HLT\na: HLT"),
+            );
+            assert_eq!(Error { line: 1, kind: ErrorKind::BadLabel(String::from("rb")) }, error("rb: HLT"));
+            assert_eq!(Error { line: 1, kind: ErrorKind::BadOperand(String::from("[rb 1]")) }, error("OUT [rb 1]"));
+            assert_eq!(Error { line: 1, kind: ErrorKind::BadExpression(String::from("1 +")) }, error("OUT 1 +"));
+            assert_eq!(Error { line: 1, kind: ErrorKind::BadExpression(String::from("aé")) }, error("OUT [aé]"));
+            assert_eq!(
+                Error { line: 1, kind: ErrorKind::RecursiveConstant(String::from("A")) },
+                error(".const A B\n.const B A"),
+            );
+            assert_eq!(
+                Error { line: 1, kind: ErrorKind::BadExpression(String::from("-9223372036854775809")) },
+                error(".data -9223372036854775809"),
+            );
+            assert_eq!(Error { line: 2, kind: ErrorKind::TooLong }, error(&format!("HLT\n.zero {}", Value::MAX)));
+            assert_eq!(Error { line: 1, kind: ErrorKind::TooLong }, error(".zero 1000000000000"));
+            assert_eq!(
+                Error { line: 2, kind: ErrorKind::AddressOverflow },
+                error(&format!(".const BIG {}\nend: .data end+BIG+1", Value::MAX)),
+            );
+            assert_eq!("line 2: unknown mnemonic NOP", error("HLT\nNOP").to_string());
+        }
+
+        #[test]
+        fn round_trip_listing() {
+            let programs: Vec<Vec<Value>> = vec![
+                vec![1, 0, 0, 0, 99],
+                vec![1002, 4, 3, 4, 33],
+                vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99],
+                vec![3, 3, 1105, 0, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+                vec![99, 42, 10001, 0, 0, 0, 1, 2, -7],
+                vec![1101, Value::MIN, Value::MAX, 7, 99, Value::MIN],
+            ];
+            for program in programs {
+                assert_eq!(program, code(&disasm::listing(&program)));
+            }
+        }
+
+        #[test]
+        fn round_trip_input() {
+            let program = crate::intcode::Program::load_from_file("data/input.txt").unwrap();
+            let memory = program.memory().to_vec();
+            assert_eq!(memory, code(&disasm::listing(&memory)));
+        }
+
+        #[test]
+        fn round_trip_source() {
+            let source = "ADD [rb+3], 7, [12]\nJF [rb-2], 0\nHLT\n.data 5, 6\n";
+            let assembled = code(source);
+            let listed: String = disasm::disassemble(&assembled)
+                .iter()
+                .map(|line| format!("{}\n", line.source()))
+                .collect();
+            assert_eq!(source, listed);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use super::memory::Memory;
+use super::Value;
+
+// Limits on how far a program may run. Exceeding one stops `Program::run`
+// with an error instead of letting it spin forever.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Budget {
+    // Total instructions executed since loading or the last reset.
+    pub steps: Option<u64>,
+    // Writes at or above this address fail.
+    pub memory: Option<usize>,
+    // Stop when the machine gets back into a state it has already been in
+    // without reading input since, which means it will never halt.
+    pub detect_loops: bool,
+}
+
+fn mix(mut x: u64) -> u64 {
+    // splitmix64's finalizer
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// What a single cell adds to the memory hash. Zero cells add nothing so
+// memory that was never written doesn't need visiting.
+pub fn cell_hash(address: usize, value: Value) -> u64 {
+    match value {
+        0 => 0,
+        v => mix(address as u64 ^ mix(v as u64)),
+    }
+}
+
+pub fn memory_hash(memory: &Memory) -> u64 {
+    memory
+        .nonzero()
+        .iter()
+        .fold(0, |hash, (address, value)| hash.wrapping_add(cell_hash(*address, *value)))
+}
+
+#[derive(Clone)]
+struct State {
+    pc: usize,
+    relative_base: Value,
+    hash: u64,
+    memory: Memory,
+}
+
+// Brent's cycle detection over machine states. The memory hash is kept up to
+// date as the program writes; states are only compared in full when the
+// hashes match.
+#[derive(Clone, Default)]
+pub struct LoopDetector {
+    hash: Option<u64>,
+    saved: Option<State>,
+    power: u64,
+    length: u64,
+}
+
+impl LoopDetector {
+    pub fn new() -> LoopDetector {
+        LoopDetector::default()
+    }
+
+    // Forget everything, e.g. after input was read or memory was changed
+    // from outside.
+    pub fn reset(&mut self) {
+        *self = LoopDetector::default();
+    }
+
+    // Account for a write of `new` over `old`.
+    pub fn write(&mut self, address: usize, old: Value, new: Value) {
+        if let Some(hash) = &mut self.hash {
+            *hash = hash.wrapping_sub(cell_hash(address, old)).wrapping_add(cell_hash(address, new));
+        }
+    }
+
+    // Look at the state after a step, returning the loop length if it has
+    // been seen before.
+    pub fn observe(&mut self, pc: usize, relative_base: Value, memory: &Memory) -> Option<u64> {
+        let hash = *self.hash.get_or_insert_with(|| memory_hash(memory));
+
+        if let Some(saved) = &self.saved {
+            if saved.pc == pc
+                && saved.relative_base == relative_base
+                && saved.hash == hash
+                && saved.memory.len() == memory.len()
+                && saved.memory.nonzero() == memory.nonzero()
+            {
+                return Some(self.length);
+            }
+        }
+
+        if self.saved.is_none() || self.power == self.length {
+            // memory clones share pages, so this is cheap until it's written
+            self.saved = Some(State { pc, relative_base, hash, memory: memory.clone() });
+            self.power = self.power.max(1) * 2;
+            self.length = 0;
+        }
+        self.length += 1;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod budget {
+        use super::super::*;
+
+        #[test]
+        fn hashes() {
+            let mut memory = Memory::from_slice(&[1, 0, 3]);
+            let mut hash = memory_hash(&memory);
+            assert_eq!(cell_hash(0, 1).wrapping_add(cell_hash(2, 3)), hash);
+            assert_eq!(0, cell_hash(7, 0));
+            assert_ne!(cell_hash(0, 3), cell_hash(2, 1));
+
+            // updating incrementally matches hashing from scratch
+            let mut detector = LoopDetector::new();
+            detector.observe(0, 0, &memory);
+            detector.write(1, 0, 9);
+            memory.set(1, 9);
+            hash = hash.wrapping_add(cell_hash(1, 9));
+            assert_eq!(Some(hash), detector.hash);
+            assert_eq!(memory_hash(&memory), hash);
+        }
+
+        #[test]
+        fn detects_repeats() {
+            let memory = Memory::from_slice(&[1, 2]);
+            let mut detector = LoopDetector::new();
+            // a cycle of three pcs
+            let found = (0..20).map(|step| detector.observe(step % 3, 0, &memory)).find(|found| found.is_some());
+            assert_eq!(Some(Some(3)), found);
+
+            // the same pc with different memory isn't a repeat
+            let mut detector = LoopDetector::new();
+            let mut memory = Memory::from_slice(&[0]);
+            for step in 1..100 {
+                detector.write(0, step - 1, step);
+                memory.set(0, step);
+                assert_eq!(None, detector.observe(0, 0, &memory));
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Generated by intcode::compile, do not edit.
+
+use crate::intcode::compile::{Compiled, Exit, State};
+use crate::intcode::Value;
+
+pub const COMPILED: Compiled = Compiled { guard: &GUARD, run };
+
+const GUARD: [(usize, Value); 43] = [
+    (0, 3), (1, 21), (2, 1008), (3, 21), (4, 8), (5, 20), (6, 1005), (7, 20),
+    (8, 22), (9, 107), (10, 8), (11, 21), (12, 20), (13, 1006), (14, 20), (15, 31),
+    (16, 1106), (17, 0), (18, 36), (22, 1002), (23, 21), (24, 125), (25, 20), (26, 4),
+    (27, 20), (28, 1105), (29, 1), (30, 46), (31, 104), (32, 999), (33, 1105), (34, 1),
+    (35, 46), (36, 1101), (37, 1000), (38, 1), (39, 20), (40, 4), (41, 20), (42, 1105),
+    (43, 1), (44, 46), (46, 99),
+];
+
+fn run(s: &mut State) -> Exit {
+    loop {
+        let result = match s.pc {
+            0 => at0(s),
+            2 => at2(s),
+            6 => at6(s),
+            9 => at9(s),
+            13 => at13(s),
+            16 => at16(s),
+            22 => at22(s),
+            26 => at26(s),
+            28 => at28(s),
+            31 => at31(s),
+            33 => at33(s),
+            36 => at36(s),
+            40 => at40(s),
+            42 => at42(s),
+            46 => at46(s),
+            _ => Err(Exit::Fallback),
+        };
+        if let Some(exit) = s.advance(result) {
+            return exit;
+        }
+    }
+}
+
+// IN [21]
+fn at0(s: &mut State) -> Result<usize, Exit> {
+    let c = s.addr(21)?;
+    let value = s.input()?;
+    s.store(c, value);
+    Ok(2)
+}
+
+// EQ [21], 8, [20]
+fn at2(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(21)?;
+    let b: Value = 8;
+    let c = s.addr(20)?;
+    s.store(c, (a == b) as Value);
+    Ok(6)
+}
+
+// JT [20], 22
+fn at6(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(20)?;
+    let b: Value = 22;
+    match a != 0 {
+        true => s.jump(b),
+        false => Ok(9),
+    }
+}
+
+// LT 8, [21], [20]
+fn at9(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 8;
+    let b: Value = s.load(21)?;
+    let c = s.addr(20)?;
+    s.store(c, (a < b) as Value);
+    Ok(13)
+}
+
+// JF [20], 31
+fn at13(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(20)?;
+    let b: Value = 31;
+    match a == 0 {
+        true => s.jump(b),
+        false => Ok(16),
+    }
+}
+
+// JF 0, 36
+fn at16(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 0;
+    let b: Value = 36;
+    match a == 0 {
+        true => s.jump(b),
+        false => Ok(19),
+    }
+}
+
+// MUL [21], 125, [20]
+fn at22(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(21)?;
+    let b: Value = 125;
+    let c = s.addr(20)?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(26)
+}
+
+// OUT [20]
+fn at26(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(20)?;
+    s.output(a);
+    Ok(28)
+}
+
+// JT 1, 46
+fn at28(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 1;
+    let b: Value = 46;
+    match a != 0 {
+        true => s.jump(b),
+        false => Ok(31),
+    }
+}
+
+// OUT 999
+fn at31(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 999;
+    s.output(a);
+    Ok(33)
+}
+
+// JT 1, 46
+fn at33(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 1;
+    let b: Value = 46;
+    match a != 0 {
+        true => s.jump(b),
+        false => Ok(36),
+    }
+}
+
+// ADD 1000, 1, [20]
+fn at36(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 1000;
+    let b: Value = 1;
+    let c = s.addr(20)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(40)
+}
+
+// OUT [20]
+fn at40(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(20)?;
+    s.output(a);
+    Ok(42)
+}
+
+// JT 1, 46
+fn at42(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 1;
+    let b: Value = 46;
+    match a != 0 {
+        true => s.jump(b),
+        false => Ok(45),
+    }
+}
+
+// HLT
+fn at46(_: &mut State) -> Result<usize, Exit> {
+    Err(Exit::Halted)
+}
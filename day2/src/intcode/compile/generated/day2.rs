@@ -0,0 +1,425 @@
+// Generated by intcode::compile, do not edit.
+
+use crate::intcode::compile::{Compiled, Exit, State};
+use crate::intcode::Value;
+
+pub const COMPILED: Compiled = Compiled { guard: &GUARD, run };
+
+const GUARD: [(usize, Value); 108] = [
+    (0, 1), (4, 1), (5, 1), (6, 2), (7, 3), (8, 1), (9, 3), (10, 4),
+    (11, 3), (12, 1), (13, 5), (14, 0), (15, 3), (16, 2), (17, 1), (18, 9),
+    (20, 1), (21, 10), (22, 19), (24, 2), (25, 9), (26, 23), (28, 1), (29, 6),
+    (30, 27), (32, 2), (33, 31), (34, 9), (36, 1), (37, 5), (38, 35), (40, 1),
+    (41, 10), (42, 39), (44, 1), (45, 10), (46, 43), (48, 2), (49, 13), (50, 47),
+    (52, 1), (53, 10), (54, 51), (56, 2), (57, 55), (58, 10), (60, 1), (61, 9),
+    (62, 59), (64, 2), (65, 6), (66, 63), (68, 1), (69, 5), (70, 67), (72, 1),
+    (73, 71), (74, 5), (76, 1), (77, 5), (78, 75), (80, 2), (81, 79), (82, 13),
+    (84, 1), (85, 83), (86, 5), (88, 2), (89, 6), (90, 87), (92, 1), (93, 5),
+    (94, 91), (96, 1), (97, 95), (98, 9), (100, 1), (101, 99), (102, 6), (104, 1),
+    (105, 103), (106, 13), (108, 1), (109, 107), (110, 5), (112, 2), (113, 111), (114, 13),
+    (116, 1), (117, 115), (118, 6), (120, 1), (121, 6), (122, 119), (124, 2), (125, 123),
+    (126, 13), (128, 1), (129, 10), (130, 127), (132, 1), (133, 131), (134, 2), (136, 1),
+    (137, 135), (138, 5), (139, 0), (140, 99),
+];
+
+fn run(s: &mut State) -> Exit {
+    loop {
+        let result = match s.pc {
+            0 => at0(s),
+            4 => at4(s),
+            8 => at8(s),
+            12 => at12(s),
+            16 => at16(s),
+            20 => at20(s),
+            24 => at24(s),
+            28 => at28(s),
+            32 => at32(s),
+            36 => at36(s),
+            40 => at40(s),
+            44 => at44(s),
+            48 => at48(s),
+            52 => at52(s),
+            56 => at56(s),
+            60 => at60(s),
+            64 => at64(s),
+            68 => at68(s),
+            72 => at72(s),
+            76 => at76(s),
+            80 => at80(s),
+            84 => at84(s),
+            88 => at88(s),
+            92 => at92(s),
+            96 => at96(s),
+            100 => at100(s),
+            104 => at104(s),
+            108 => at108(s),
+            112 => at112(s),
+            116 => at116(s),
+            120 => at120(s),
+            124 => at124(s),
+            128 => at128(s),
+            132 => at132(s),
+            136 => at136(s),
+            140 => at140(s),
+            _ => Err(Exit::Fallback),
+        };
+        if let Some(exit) = s.advance(result) {
+            return exit;
+        }
+    }
+}
+
+// ADD [0], [0], [3]
+fn at0(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(s.word(1))?;
+    let b: Value = s.load(s.word(2))?;
+    let c = s.addr(s.word(3))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(4)
+}
+
+// ADD [1], [2], [3]
+fn at4(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(1)?;
+    let b: Value = s.load(2)?;
+    let c = s.addr(3)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(8)
+}
+
+// ADD [3], [4], [3]
+fn at8(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(3)?;
+    let b: Value = s.load(4)?;
+    let c = s.addr(3)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(12)
+}
+
+// ADD [5], [0], [3]
+fn at12(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(5)?;
+    let b: Value = s.load(0)?;
+    let c = s.addr(3)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(16)
+}
+
+// MUL [1], [9], [19]
+fn at16(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(1)?;
+    let b: Value = s.load(9)?;
+    let c = s.addr(s.word(19))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(20)
+}
+
+// ADD [10], [19], [23]
+fn at20(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(10)?;
+    let b: Value = s.load(19)?;
+    let c = s.addr(s.word(23))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(24)
+}
+
+// MUL [9], [23], [27]
+fn at24(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(9)?;
+    let b: Value = s.load(23)?;
+    let c = s.addr(s.word(27))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(28)
+}
+
+// ADD [6], [27], [31]
+fn at28(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(6)?;
+    let b: Value = s.load(27)?;
+    let c = s.addr(s.word(31))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(32)
+}
+
+// MUL [31], [9], [35]
+fn at32(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(31)?;
+    let b: Value = s.load(9)?;
+    let c = s.addr(s.word(35))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(36)
+}
+
+// ADD [5], [35], [39]
+fn at36(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(5)?;
+    let b: Value = s.load(35)?;
+    let c = s.addr(s.word(39))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(40)
+}
+
+// ADD [10], [39], [43]
+fn at40(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(10)?;
+    let b: Value = s.load(39)?;
+    let c = s.addr(s.word(43))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(44)
+}
+
+// ADD [10], [43], [47]
+fn at44(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(10)?;
+    let b: Value = s.load(43)?;
+    let c = s.addr(s.word(47))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(48)
+}
+
+// MUL [13], [47], [51]
+fn at48(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(13)?;
+    let b: Value = s.load(47)?;
+    let c = s.addr(s.word(51))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(52)
+}
+
+// ADD [10], [51], [55]
+fn at52(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(10)?;
+    let b: Value = s.load(51)?;
+    let c = s.addr(s.word(55))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(56)
+}
+
+// MUL [55], [10], [59]
+fn at56(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(55)?;
+    let b: Value = s.load(10)?;
+    let c = s.addr(s.word(59))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(60)
+}
+
+// ADD [9], [59], [63]
+fn at60(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(9)?;
+    let b: Value = s.load(59)?;
+    let c = s.addr(s.word(63))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(64)
+}
+
+// MUL [6], [63], [67]
+fn at64(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(6)?;
+    let b: Value = s.load(63)?;
+    let c = s.addr(s.word(67))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(68)
+}
+
+// ADD [5], [67], [71]
+fn at68(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(5)?;
+    let b: Value = s.load(67)?;
+    let c = s.addr(s.word(71))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(72)
+}
+
+// ADD [71], [5], [75]
+fn at72(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(71)?;
+    let b: Value = s.load(5)?;
+    let c = s.addr(s.word(75))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(76)
+}
+
+// ADD [5], [75], [79]
+fn at76(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(5)?;
+    let b: Value = s.load(75)?;
+    let c = s.addr(s.word(79))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(80)
+}
+
+// MUL [79], [13], [83]
+fn at80(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(79)?;
+    let b: Value = s.load(13)?;
+    let c = s.addr(s.word(83))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(84)
+}
+
+// ADD [83], [5], [87]
+fn at84(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(83)?;
+    let b: Value = s.load(5)?;
+    let c = s.addr(s.word(87))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(88)
+}
+
+// MUL [6], [87], [91]
+fn at88(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(6)?;
+    let b: Value = s.load(87)?;
+    let c = s.addr(s.word(91))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(92)
+}
+
+// ADD [5], [91], [95]
+fn at92(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(5)?;
+    let b: Value = s.load(91)?;
+    let c = s.addr(s.word(95))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(96)
+}
+
+// ADD [95], [9], [99]
+fn at96(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(95)?;
+    let b: Value = s.load(9)?;
+    let c = s.addr(s.word(99))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(100)
+}
+
+// ADD [99], [6], [103]
+fn at100(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(99)?;
+    let b: Value = s.load(6)?;
+    let c = s.addr(s.word(103))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(104)
+}
+
+// ADD [103], [13], [107]
+fn at104(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(103)?;
+    let b: Value = s.load(13)?;
+    let c = s.addr(s.word(107))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(108)
+}
+
+// ADD [107], [5], [111]
+fn at108(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(107)?;
+    let b: Value = s.load(5)?;
+    let c = s.addr(s.word(111))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(112)
+}
+
+// MUL [111], [13], [115]
+fn at112(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(111)?;
+    let b: Value = s.load(13)?;
+    let c = s.addr(s.word(115))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(116)
+}
+
+// ADD [115], [6], [119]
+fn at116(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(115)?;
+    let b: Value = s.load(6)?;
+    let c = s.addr(s.word(119))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(120)
+}
+
+// ADD [6], [119], [123]
+fn at120(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(6)?;
+    let b: Value = s.load(119)?;
+    let c = s.addr(s.word(123))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(124)
+}
+
+// MUL [123], [13], [127]
+fn at124(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(123)?;
+    let b: Value = s.load(13)?;
+    let c = s.addr(s.word(127))?;
+    let value = a.checked_mul(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(128)
+}
+
+// ADD [10], [127], [131]
+fn at128(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(10)?;
+    let b: Value = s.load(127)?;
+    let c = s.addr(s.word(131))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(132)
+}
+
+// ADD [131], [2], [135]
+fn at132(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(131)?;
+    let b: Value = s.load(2)?;
+    let c = s.addr(s.word(135))?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(136)
+}
+
+// ADD [135], [5], [0]
+fn at136(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(135)?;
+    let b: Value = s.load(5)?;
+    let c = s.addr(0)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(140)
+}
+
+// HLT
+fn at140(_: &mut State) -> Result<usize, Exit> {
+    Err(Exit::Halted)
+}
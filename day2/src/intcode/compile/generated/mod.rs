@@ -0,0 +1,4 @@
+pub mod compare;
+pub mod day2;
+pub mod quine;
+pub mod self_modifying;
@@ -0,0 +1,76 @@
+// Generated by intcode::compile, do not edit.
+
+use crate::intcode::compile::{Compiled, Exit, State};
+use crate::intcode::Value;
+
+pub const COMPILED: Compiled = Compiled { guard: &GUARD, run };
+
+const GUARD: [(usize, Value); 16] = [
+    (0, 109), (1, 1), (2, 204), (3, -1), (4, 1001), (5, 100), (6, 1), (7, 100),
+    (8, 1008), (9, 100), (10, 16), (11, 101), (12, 1006), (13, 101), (14, 0), (15, 99),
+];
+
+fn run(s: &mut State) -> Exit {
+    loop {
+        let result = match s.pc {
+            0 => at0(s),
+            2 => at2(s),
+            4 => at4(s),
+            8 => at8(s),
+            12 => at12(s),
+            15 => at15(s),
+            _ => Err(Exit::Fallback),
+        };
+        if let Some(exit) = s.advance(result) {
+            return exit;
+        }
+    }
+}
+
+// ARB 1
+fn at0(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 1;
+    s.adjust(a)?;
+    Ok(2)
+}
+
+// OUT [rb-1]
+fn at2(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(s.rel(-1)?)?;
+    s.output(a);
+    Ok(4)
+}
+
+// ADD [100], 1, [100]
+fn at4(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(100)?;
+    let b: Value = 1;
+    let c = s.addr(100)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(8)
+}
+
+// EQ [100], 16, [101]
+fn at8(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(100)?;
+    let b: Value = 16;
+    let c = s.addr(101)?;
+    s.store(c, (a == b) as Value);
+    Ok(12)
+}
+
+// JF [101], 0
+fn at12(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(101)?;
+    let b: Value = 0;
+    match a == 0 {
+        true => s.jump(b),
+        false => Ok(15),
+    }
+}
+
+// HLT
+fn at15(_: &mut State) -> Result<usize, Exit> {
+    Err(Exit::Halted)
+}
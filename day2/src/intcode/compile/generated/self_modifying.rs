@@ -0,0 +1,52 @@
+// Generated by intcode::compile, do not edit.
+
+use crate::intcode::compile::{Compiled, Exit, State};
+use crate::intcode::Value;
+
+pub const COMPILED: Compiled = Compiled { guard: &GUARD, run };
+
+const GUARD: [(usize, Value); 10] = [
+    (0, 109), (1, 6), (2, 21101), (3, 0), (4, 99), (5, 0), (6, 1), (7, 0),
+    (8, 0), (9, 0),
+];
+
+fn run(s: &mut State) -> Exit {
+    loop {
+        let result = match s.pc {
+            0 => at0(s),
+            2 => at2(s),
+            6 => at6(s),
+            _ => Err(Exit::Fallback),
+        };
+        if let Some(exit) = s.advance(result) {
+            return exit;
+        }
+    }
+}
+
+// ARB 6
+fn at0(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 6;
+    s.adjust(a)?;
+    Ok(2)
+}
+
+// ADD 0, 99, [rb+0]
+fn at2(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = 0;
+    let b: Value = 99;
+    let c = s.addr(s.rel(0)?)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(6)
+}
+
+// ADD [0], [0], [0]
+fn at6(s: &mut State) -> Result<usize, Exit> {
+    let a: Value = s.load(0)?;
+    let b: Value = s.load(0)?;
+    let c = s.addr(0)?;
+    let value = a.checked_add(b).ok_or(Exit::Fallback)?;
+    s.store(c, value);
+    Ok(10)
+}
@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use super::disasm;
+use super::memory::Memory;
+use super::snapshot::Snapshot;
+use super::{op_info, Error, Instruction, Mode, Program, Status, Value};
+use super::{OP_ADD, OP_ARB, OP_EQ, OP_HLT, OP_IN, OP_JF, OP_JT, OP_LT, OP_MUL, OP_OUT};
+
+#[cfg(test)]
+mod generated;
+
+const DEFAULT_PRELUDE: &str = "aoc2019_2::intcode";
+
+// Why compiled code handed control back.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Exit {
+    Halted,
+    WaitingForInput,
+    // Either the next instruction wasn't compiled, it would fail, or code the
+    // compiled functions depend on was overwritten. The interpreter picks up
+    // from the current pc.
+    Fallback,
+}
+
+// Machine state as seen by compiled code. Only buffered input and output is
+// available, attached sources and sinks are left to the interpreter.
+pub struct State {
+    pub memory: Memory,
+    pub pc: usize,
+    pub relative_base: Value,
+    pub steps: u64,
+    pub inputs: VecDeque<Value>,
+    pub outputs: Vec<Value>,
+    guard: Vec<bool>,
+    modified: bool,
+}
+
+impl State {
+    fn new(snapshot: Snapshot, guard: &[(usize, Value)]) -> State {
+        let mut guarded = vec![false; guard.iter().map(|(addr, _)| addr + 1).max().unwrap_or(0)];
+        for (addr, _) in guard {
+            guarded[*addr] = true;
+        }
+        State {
+            memory: snapshot.memory,
+            pc: snapshot.pc,
+            relative_base: snapshot.relative_base,
+            steps: snapshot.steps,
+            inputs: snapshot.inputs.into_iter().collect(),
+            outputs: snapshot.outputs,
+            guard: guarded,
+            modified: false,
+        }
+    }
+
+    fn into_snapshot(self) -> Snapshot {
+        Snapshot {
+            memory: self.memory,
+            pc: self.pc,
+            relative_base: self.relative_base,
+            steps: self.steps,
+            // an HLT is left for `execute` to report
+            halted: false,
+            inputs: self.inputs.into_iter().collect(),
+            outputs: self.outputs,
+        }
+    }
+
+    // The helpers below are what generated code is written in terms of.
+
+    pub fn word(&self, addr: usize) -> Value {
+        self.memory.get(addr)
+    }
+
+    pub fn addr(&self, addr: Value) -> Result<usize, Exit> {
+        match addr {
+            a if a < 0 => Err(Exit::Fallback),
+            a => Ok(a as usize),
+        }
+    }
+
+    pub fn rel(&self, offset: Value) -> Result<Value, Exit> {
+        self.relative_base.checked_add(offset).ok_or(Exit::Fallback)
+    }
+
+    pub fn load(&self, addr: Value) -> Result<Value, Exit> {
+        Ok(self.memory.get(self.addr(addr)?))
+    }
+
+    pub fn store(&mut self, addr: usize, value: Value) {
+        if self.guard.get(addr).cloned().unwrap_or(false) {
+            self.modified = true;
+        }
+        self.memory.set(addr, value);
+    }
+
+    pub fn input(&mut self) -> Result<Value, Exit> {
+        self.inputs.pop_front().ok_or(Exit::WaitingForInput)
+    }
+
+    pub fn output(&mut self, value: Value) {
+        self.outputs.push(value);
+    }
+
+    pub fn jump(&self, target: Value) -> Result<usize, Exit> {
+        self.addr(target)
+    }
+
+    pub fn adjust(&mut self, offset: Value) -> Result<(), Exit> {
+        self.relative_base = self.rel(offset)?;
+        Ok(())
+    }
+
+    // Finish an instruction, returning why to stop if compiled code can't
+    // carry on.
+    pub fn advance(&mut self, result: Result<usize, Exit>) -> Option<Exit> {
+        match result {
+            Ok(pc) => {
+                self.pc = pc;
+                self.steps += 1;
+                match self.modified {
+                    true => Some(Exit::Fallback),
+                    false => None,
+                }
+            }
+            Err(Exit::Halted) => {
+                self.steps += 1;
+                Some(Exit::Halted)
+            }
+            Err(exit) => Some(exit),
+        }
+    }
+}
+
+// A compiled program: the code words it was compiled against and the
+// function to run.
+pub struct Compiled {
+    pub guard: &'static [(usize, Value)],
+    pub run: fn(&mut State) -> Exit,
+}
+
+// Run `program` with compiled code for as long as it can, then let the
+// interpreter finish. Programs whose code doesn't match what was compiled,
+// that need watching instruction by instruction, that write output to a
+// sink, which compiled code can't reach, or that already halted are left to
+// the interpreter.
+pub fn execute(program: &mut Program, compiled: &Compiled) -> Result<Status, Error> {
+    let mismatched = compiled.guard.iter().any(|(addr, value)| program.memory().get(*addr) != *value);
+    if mismatched || program.needs_interpreter() || program.output.is_some() || program.halted {
+        return program.run();
+    }
+
+    let mut state = State::new(program.snapshot(), compiled.guard);
+    let exit = (compiled.run)(&mut state);
+    program.restore(&state.into_snapshot());
+    match exit {
+        Exit::Halted => {
+            program.halted = true;
+            Ok(Status::Halted)
+        }
+        Exit::WaitingForInput | Exit::Fallback => program.run(),
+    }
+}
+
+// Translates an intcode image into a Rust module exporting a `COMPILED`
+// constant for `execute`.
+//
+// Operand words are baked into the generated code unless they may change at
+// run time: words the caller marks dynamic (like day 2's noun and verb) and
+// words the program writes to through a fixed address are read from memory
+// instead. Opcode words the caller marks dynamic aren't compiled at all.
+// Anything else that ends up writing over baked code, opcodes included,
+// makes compiled code fall back to the interpreter.
+pub struct Compiler {
+    memory: Vec<Value>,
+    dynamic: BTreeSet<usize>,
+    prelude: String,
+}
+
+impl Compiler {
+    pub fn new(memory: Vec<Value>) -> Compiler {
+        Compiler {
+            memory,
+            dynamic: BTreeSet::new(),
+            prelude: DEFAULT_PRELUDE.to_string(),
+        }
+    }
+
+    pub fn dynamic(mut self, addr: usize) -> Compiler {
+        self.dynamic.insert(addr);
+        self
+    }
+
+    // The module path generated code imports the intcode types from.
+    pub fn prelude(mut self, path: &str) -> Compiler {
+        self.prelude = path.to_string();
+        self
+    }
+
+    fn word(&self, addr: usize) -> Value {
+        self.memory.get(addr).cloned().unwrap_or(0)
+    }
+
+    // Instructions reachable from pc 0 that can be compiled. Operand words in
+    // `dynamic` may change, opcode words only if the caller said so.
+    fn reachable(&self) -> BTreeMap<usize, Instruction> {
+        let mut found = BTreeMap::new();
+        let mut pending = vec![0];
+        while let Some(pc) = pending.pop() {
+            if pc >= self.memory.len() || found.contains_key(&pc) || self.dynamic.contains(&pc) {
+                continue;
+            }
+            if disasm::decode_at(&self.memory, pc).is_none() {
+                continue;
+            }
+            let inst = match Instruction::decode(pc, self.memory[pc]) {
+                Ok(inst) => inst,
+                Err(_) => continue,
+            };
+            let params = op_info(inst.op).map(|info| info.params).unwrap_or(0);
+            let writes = op_info(inst.op).map(|info| info.writes).unwrap_or(false);
+            if writes && inst.mode(params) == Mode::Immediate {
+                continue;
+            }
+
+            match inst.op {
+                OP_HLT => (),
+                OP_JT | OP_JF => {
+                    pending.push(pc + 3);
+                    // the target's current value is only a hint for finding
+                    // code, the jump itself still reads it at run time
+                    let target = match inst.mode(2) {
+                        Mode::Immediate => Some(self.word(pc + 2)),
+                        Mode::Position if self.word(pc + 2) >= 0 => Some(self.word(self.word(pc + 2) as usize)),
+                        _ => None,
+                    };
+                    if let Some(t) = target.filter(|t| *t >= 0) {
+                        pending.push(t as usize);
+                    }
+                }
+                _ => pending.push(pc + params + 1),
+            }
+            found.insert(pc, inst);
+        }
+        found
+    }
+
+    // Addresses written through a baked position mode operand.
+    fn fixed_writes(&self, code: &BTreeMap<usize, Instruction>, dynamic: &BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut writes = BTreeSet::new();
+        for (pc, inst) in code {
+            let info = match op_info(inst.op) {
+                Some(info) if info.writes => info,
+                _ => continue,
+            };
+            let operand = pc + info.params;
+            if inst.mode(info.params) == Mode::Position && !dynamic.contains(&operand) && self.word(operand) >= 0 {
+                writes.insert(self.word(operand) as usize);
+            }
+        }
+        writes
+    }
+
+    fn analyse(&self) -> (BTreeMap<usize, Instruction>, BTreeSet<usize>) {
+        let code = self.reachable();
+        let mut dynamic = self.dynamic.clone();
+        // a write target that's itself an operand of a write has to be read
+        // at run time, which can turn fixed writes into unknown ones
+        loop {
+            let writes = self.fixed_writes(&code, &dynamic);
+            if writes.is_subset(&dynamic) {
+                return (code, dynamic);
+            }
+            dynamic.extend(writes);
+        }
+    }
+
+    pub fn generate(&self) -> String {
+        let (code, dynamic) = self.analyse();
+
+        let mut guard = Vec::new();
+        for (pc, inst) in &code {
+            let params = op_info(inst.op).map(|info| info.params).unwrap_or(0);
+            guard.push(*pc);
+            guard.extend((pc + 1..=pc + params).filter(|addr| !dynamic.contains(addr)));
+        }
+
+        let mut out = String::new();
+        let _ = writeln!(out, "// Generated by intcode::compile, do not edit.");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "use {}::compile::{{Compiled, Exit, State}};", self.prelude);
+        let _ = writeln!(out, "use {}::Value;", self.prelude);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "pub const COMPILED: Compiled = Compiled {{ guard: &GUARD, run }};");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "const GUARD: [(usize, Value); {}] = [", guard.len());
+        for chunk in guard.chunks(8) {
+            let cells: Vec<String> = chunk.iter().map(|addr| format!("({}, {})", addr, self.word(*addr))).collect();
+            let _ = writeln!(out, "    {},", cells.join(", "));
+        }
+        let _ = writeln!(out, "];");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "fn run(s: &mut State) -> Exit {{");
+        let _ = writeln!(out, "    loop {{");
+        let _ = writeln!(out, "        let result = match s.pc {{");
+        for pc in code.keys() {
+            let _ = writeln!(out, "            {} => at{}(s),", pc, pc);
+        }
+        let _ = writeln!(out, "            _ => Err(Exit::Fallback),");
+        let _ = writeln!(out, "        }};");
+        let _ = writeln!(out, "        if let Some(exit) = s.advance(result) {{");
+        let _ = writeln!(out, "            return exit;");
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out, "}}");
+
+        for (pc, inst) in &code {
+            let _ = writeln!(out);
+            if let Some(line) = disasm::decode_at(&self.memory, *pc) {
+                let _ = writeln!(out, "// {}", line.source());
+            }
+            let state = if inst.op == OP_HLT { "_" } else { "s" };
+            let _ = writeln!(out, "fn at{}({}: &mut State) -> Result<usize, Exit> {{", pc, state);
+            for line in self.body(*pc, inst, &dynamic) {
+                let _ = writeln!(out, "    {}", line);
+            }
+            let _ = writeln!(out, "}}");
+        }
+        out
+    }
+
+    // The raw operand: baked in, or read from memory when it may change.
+    fn operand(&self, pc: usize, param: usize, dynamic: &BTreeSet<usize>) -> String {
+        match dynamic.contains(&(pc + param)) {
+            true => format!("s.word({})", pc + param),
+            false => self.word(pc + param).to_string(),
+        }
+    }
+
+    fn read(&self, pc: usize, inst: &Instruction, param: usize, dynamic: &BTreeSet<usize>) -> String {
+        let operand = self.operand(pc, param, dynamic);
+        match inst.mode(param) {
+            Mode::Immediate => operand,
+            Mode::Position => format!("s.load({})?", operand),
+            Mode::Relative => format!("s.load(s.rel({})?)?", operand),
+        }
+    }
+
+    fn write_addr(&self, pc: usize, inst: &Instruction, param: usize, dynamic: &BTreeSet<usize>) -> String {
+        let operand = self.operand(pc, param, dynamic);
+        match inst.mode(param) {
+            Mode::Relative => format!("s.addr(s.rel({})?)?", operand),
+            _ => format!("s.addr({})?", operand),
+        }
+    }
+
+    // Each instruction checks everything that could fail before changing
+    // any state, so falling back leaves the interpreter to report the error.
+    fn body(&self, pc: usize, inst: &Instruction, dynamic: &BTreeSet<usize>) -> Vec<String> {
+        let a = || format!("let a: Value = {};", self.read(pc, inst, 1, dynamic));
+        let b = || format!("let b: Value = {};", self.read(pc, inst, 2, dynamic));
+        match inst.op {
+            OP_ADD | OP_MUL => vec![
+                a(),
+                b(),
+                format!("let c = {};", self.write_addr(pc, inst, 3, dynamic)),
+                format!(
+                    "let value = a.{}(b).ok_or(Exit::Fallback)?;",
+                    if inst.op == OP_ADD { "checked_add" } else { "checked_mul" },
+                ),
+                "s.store(c, value);".to_string(),
+                format!("Ok({})", pc + 4),
+            ],
+            OP_LT | OP_EQ => vec![
+                a(),
+                b(),
+                format!("let c = {};", self.write_addr(pc, inst, 3, dynamic)),
+                format!("s.store(c, (a {} b) as Value);", if inst.op == OP_LT { "<" } else { "==" }),
+                format!("Ok({})", pc + 4),
+            ],
+            OP_IN => vec![
+                format!("let c = {};", self.write_addr(pc, inst, 1, dynamic)),
+                "let value = s.input()?;".to_string(),
+                "s.store(c, value);".to_string(),
+                format!("Ok({})", pc + 2),
+            ],
+            OP_OUT => vec![
+                a(),
+                "s.output(a);".to_string(),
+                format!("Ok({})", pc + 2),
+            ],
+            OP_JT | OP_JF => vec![
+                a(),
+                b(),
+                format!("match a {} 0 {{", if inst.op == OP_JT { "!=" } else { "==" }),
+                "    true => s.jump(b),".to_string(),
+                format!("    false => Ok({}),", pc + 3),
+                "}".to_string(),
+            ],
+            OP_ARB => vec![
+                a(),
+                "s.adjust(a)?;".to_string(),
+                format!("Ok({})", pc + 2),
+            ],
+            _ => vec!["Err(Exit::Halted)".to_string()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod compile {
+        use super::super::*;
+        use super::super::generated;
+        use super::super::super::io;
+        use super::super::super::testing;
+
+        const COMPARE: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,\
+            20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+        const QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        // Writes 99 over the ADD at 6 through the relative base.
+        const SELF_MODIFYING: &str = "109,6,21101,0,99,0,1,0,0,0";
+
+        fn parse(code: &str) -> Vec<Value> {
+            code.split(',').map(|v| v.parse().unwrap()).collect()
+        }
+
+        fn day2() -> Vec<Value> {
+            Program::load_from_file("data/input.txt").unwrap().memory().to_vec()
+        }
+
+        fn compilers() -> Vec<(&'static str, Compiler, &'static str)> {
+            let prelude = "crate::intcode";
+            vec![
+                ("day2", Compiler::new(day2()).dynamic(1).dynamic(2).prelude(prelude), include_str!("generated/day2.rs")),
+                ("compare", Compiler::new(parse(COMPARE)).prelude(prelude), include_str!("generated/compare.rs")),
+                ("quine", Compiler::new(parse(QUINE)).prelude(prelude), include_str!("generated/quine.rs")),
+                (
+                    "self_modifying",
+                    Compiler::new(parse(SELF_MODIFYING)).prelude(prelude),
+                    include_str!("generated/self_modifying.rs"),
+                ),
+            ]
+        }
+
+        fn differential(code: Vec<Value>, compiled: &Compiled, inputs: &[Value]) -> (Program, Vec<Value>) {
+            testing::differential(code, inputs, |program| execute(program, compiled))
+        }
+
+        #[test]
+        fn generated_is_current() {
+            for (name, compiler, checked_in) in compilers() {
+                assert!(compiler.generate() == checked_in, "generated/{}.rs is out of date", name);
+            }
+        }
+
+        #[test]
+        fn day2_matches() {
+            for (noun, verb) in [(12, 2), (71, 95), (0, 0), (99, 99)] {
+                let mut code = day2();
+                code[1] = noun;
+                code[2] = verb;
+                let mut interpreted = Program::load(day2());
+                let expected = interpreted.call(noun, verb).unwrap();
+                let (program, _) = differential(code, &generated::day2::COMPILED, &[]);
+                assert_eq!(expected, program.memory().get(0));
+            }
+
+            // everything but the final halt runs compiled, the last write
+            // lands on the opcode at 0
+            let compiled = &generated::day2::COMPILED;
+            let mut state = State::new(Program::load(day2()).snapshot(), compiled.guard);
+            assert_eq!(Exit::Fallback, (compiled.run)(&mut state));
+            assert_eq!(140, state.pc);
+        }
+
+        #[test]
+        fn compare_matches() {
+            for input in [7, 8, 9] {
+                differential(parse(COMPARE), &generated::compare::COMPILED, &[input]);
+            }
+            // waits for input, then carries on when it arrives
+            let mut program = Program::load(parse(COMPARE));
+            assert_eq!(Ok(Status::WaitingForInput), execute(&mut program, &generated::compare::COMPILED));
+            program.push_input(8);
+            assert_eq!(Ok(Status::Halted), execute(&mut program, &generated::compare::COMPILED));
+            assert_eq!(vec![1000], program.take_output());
+        }
+
+        #[test]
+        fn quine_matches() {
+            let (_, output) = differential(parse(QUINE), &generated::quine::COMPILED, &[]);
+            assert_eq!(parse(QUINE), output);
+        }
+
+        #[test]
+        fn output_sink() {
+            let mut interpreted = Program::load(parse(QUINE));
+            let expected = io::Queue::new();
+            interpreted.set_output(expected.clone());
+            assert_eq!(Ok(Status::Halted), interpreted.run());
+
+            // the first value is already buffered, the rest go to the sink
+            let mut program = Program::load(parse(QUINE));
+            assert_eq!(Ok(Status::Running), program.step());
+            assert_eq!(Ok(Status::Running), program.step());
+            let outputs = io::Queue::new();
+            program.set_output(outputs.clone());
+            assert_eq!(Ok(Status::Halted), execute(&mut program, &generated::quine::COMPILED));
+            assert_eq!(vec![109], program.take_output());
+            assert_eq!(expected.drain()[1..], outputs.drain()[..]);
+        }
+
+        #[test]
+        fn self_modifying_falls_back() {
+            let compiled = &generated::self_modifying::COMPILED;
+            let mut state = State::new(Program::load(parse(SELF_MODIFYING)).snapshot(), compiled.guard);
+            assert_eq!(Exit::Fallback, (compiled.run)(&mut state));
+            assert_eq!(6, state.pc);
+            differential(parse(SELF_MODIFYING), compiled, &[]);
+        }
+
+        #[test]
+        fn mismatched_code_is_interpreted() {
+            // the equals at 2 becomes a less than
+            let mut code = parse(COMPARE);
+            code[2] = 1007;
+            differential(code, &generated::compare::COMPILED, &[5]);
+        }
+
+        #[test]
+        fn analysis() {
+            // the noun and verb and anything written at a fixed address are
+            // read at run time
+            let source = Compiler::new(day2()).dynamic(1).dynamic(2).generate();
+            assert!(source.contains("let a: Value = s.load(s.word(1))?;"));
+            assert!(source.contains("let c = s.addr(s.word(3))?;"));
+            // an opcode written at a fixed address is still compiled, but
+            // guarded
+            let source = Compiler::new(vec![1101, 1, 98, 4, 1, 0, 0, 0]).generate();
+            assert!(source.contains("fn at4("));
+            assert!(source.contains("(4, 1)"));
+            // unless the caller says it will change
+            let source = Compiler::new(vec![1101, 1, 98, 4, 1, 0, 0, 0]).dynamic(4).generate();
+            assert!(!source.contains("fn at4("));
+            // immediate destinations and unknown opcodes aren't compiled
+            assert!(!Compiler::new(vec![11101, 1, 1, 0, 99]).generate().contains("fn at0("));
+            assert!(!Compiler::new(vec![42]).generate().contains("fn at0("));
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::{disasm, Error, Program, Status, Value};
+
+// How many instructions can be stepped back over.
+const HISTORY_LIMIT: usize = 1_000_000;
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until a breakpoint, watchpoint, halt or input wait
+  rs, reverse-step [n] undo n instructions (default 1)
+  rc, reverse-continue run backwards to a breakpoint, watchpoint or the
+                       start of the history
+  b, break <addr>      set a breakpoint
+  d, delete <addr>     remove a breakpoint
+  w, watch <addr>      stop after <addr> is written
+  u, unwatch <addr>    remove a watchpoint
+  i, info              list breakpoints and watchpoints
+  r, regs              show pc, relative base and the current instruction
+  l, list [addr] [n]   disassemble n instructions (default 10) from addr or pc
+  p, peek <addr> [n]   show n memory cells (default 1)
+  poke <addr> <value>  write a memory cell (clears the history)
+  who-wrote <addr>     show the last instruction that wrote <addr>
+  in <value>...        queue input values
+  reset                reload the original program
+  q, quit              exit";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Stop {
+    // A single step finished without anything else happening.
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { address: usize, pc: usize },
+    Halted,
+    WaitingForInput,
+    Error(Error),
+    // Stepping backwards ran out of history.
+    HistoryStart,
+}
+
+pub struct Debugger {
+    program: Program,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+}
+
+fn parse_addr(arg: Option<&str>) -> Result<usize, String> {
+    match arg {
+        Some(arg) => arg.parse::<usize>().map_err(|_| format!("invalid address: {}", arg)),
+        None => Err(String::from("missing address")),
+    }
+}
+
+fn parse_value(arg: &str) -> Result<Value, String> {
+    arg.parse::<Value>().map_err(|_| format!("invalid value: {}", arg))
+}
+
+impl Debugger {
+    pub fn new(mut program: Program) -> Debugger {
+        program.enable_history(HISTORY_LIMIT);
+        Debugger {
+            program,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut Program {
+        &mut self.program
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize) {
+        self.watchpoints.insert(address);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    pub fn step(&mut self) -> Stop {
+        let pc = self.program.pc();
+        match self.program.step() {
+            Ok(Status::Running) => match self.program.last_write() {
+                Some(address) if self.watchpoints.contains(&address) => Stop::Watchpoint { address, pc },
+                _ => Stop::Stepped,
+            }
+            Ok(Status::Halted) => Stop::Halted,
+            Ok(Status::WaitingForInput) => Stop::WaitingForInput,
+            Err(e) => Stop::Error(e),
+        }
+    }
+
+    // Keep stepping until something interesting happens. The instruction at
+    // the current pc always runs, so continuing from a breakpoint works.
+    pub fn cont(&mut self) -> Stop {
+        loop {
+            match self.step() {
+                Stop::Stepped => match self.program.pc() {
+                    pc if self.breakpoints.contains(&pc) => return Stop::Breakpoint(pc),
+                    _ => continue,
+                }
+                stop => return stop,
+            }
+        }
+    }
+
+    pub fn reverse_step(&mut self) -> Stop {
+        match self.program.step_back() {
+            true => Stop::Stepped,
+            false => Stop::HistoryStart,
+        }
+    }
+
+    // Step backwards until about to run an instruction at a breakpoint or
+    // one that writes a watched address.
+    pub fn reverse_cont(&mut self) -> Stop {
+        loop {
+            let undo = match self.program.history().and_then(|history| history.iter().next_back()) {
+                Some(undo) => *undo,
+                None => return Stop::HistoryStart,
+            };
+            self.program.step_back();
+            match undo.write {
+                Some((address, _)) if self.watchpoints.contains(&address) => {
+                    return Stop::Watchpoint { address, pc: undo.pc }
+                }
+                _ if self.breakpoints.contains(&undo.pc) => return Stop::Breakpoint(undo.pc),
+                _ => (),
+            }
+        }
+    }
+
+    // Disassemble the instruction at `address` without copying all of memory.
+    // The window stops at the last address, so an instruction that would run
+    // past it shows up as data.
+    fn describe(&self, address: usize) -> disasm::Line {
+        let memory = self.program.memory();
+        let window: Vec<Value> = (address..=address.saturating_add(3)).map(|a| memory.get(a)).collect();
+        let mut line = match disasm::decode_at(&window, 0) {
+            Some(line) => line,
+            None => disasm::disassemble(&window[..1]).remove(0),
+        };
+        line.address = address;
+        line
+    }
+
+    fn regs(&self) -> String {
+        format!(
+            "pc: {}  rb: {}\n{}\n",
+            self.program.pc(),
+            self.program.relative_base(),
+            self.describe(self.program.pc()),
+        )
+    }
+
+    fn report(&mut self, stop: Stop) -> String {
+        let mut out = String::new();
+        for value in self.program.take_output() {
+            let _ = writeln!(out, "output: {}", value);
+        }
+        match stop {
+            Stop::Stepped => (),
+            Stop::Breakpoint(pc) => { let _ = writeln!(out, "breakpoint at {}", pc); }
+            Stop::Watchpoint { address, pc } => {
+                let _ = writeln!(out, "watchpoint: {} written by instruction at {}", address, pc);
+            }
+            Stop::Halted => out.push_str("halted\n"),
+            Stop::WaitingForInput => out.push_str("waiting for input\n"),
+            Stop::Error(e) => { let _ = writeln!(out, "error: {}", e); }
+            Stop::HistoryStart => out.push_str("reached the start of the history\n"),
+        }
+        out.push_str(&self.regs());
+        out
+    }
+
+    fn list(&self, address: usize, count: usize) -> String {
+        let mut out = String::new();
+        let mut address = address;
+        for _ in 0..count {
+            let line = self.describe(address);
+            let _ = writeln!(out, "{}{}", if line.address == self.program.pc() { "> " } else { "  " }, line);
+            address = match address.checked_add(line.raw.len()) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        out
+    }
+
+    // Run a single REPL command and return what it printed.
+    pub fn exec(&mut self, command: &str) -> Result<String, String> {
+        let mut args = command.split_whitespace();
+        let cmd = match args.next() {
+            Some(cmd) => cmd,
+            None => return Ok(String::new()),
+        };
+
+        match cmd {
+            "s" | "step" => {
+                let count = match args.next() {
+                    Some(n) => n.parse::<usize>().map_err(|_| format!("invalid count: {}", n))?,
+                    None => 1,
+                };
+                let mut stop = Stop::Stepped;
+                for _ in 0..count {
+                    stop = self.step();
+                    if stop != Stop::Stepped {
+                        break;
+                    }
+                }
+                Ok(self.report(stop))
+            }
+            "c" | "continue" => {
+                let stop = self.cont();
+                Ok(self.report(stop))
+            }
+            "rs" | "reverse-step" => {
+                let count = match args.next() {
+                    Some(n) => n.parse::<usize>().map_err(|_| format!("invalid count: {}", n))?,
+                    None => 1,
+                };
+                let mut stop = Stop::Stepped;
+                for _ in 0..count {
+                    stop = self.reverse_step();
+                    if stop != Stop::Stepped {
+                        break;
+                    }
+                }
+                Ok(self.report(stop))
+            }
+            "rc" | "reverse-continue" => {
+                let stop = self.reverse_cont();
+                Ok(self.report(stop))
+            }
+            "b" | "break" => {
+                let address = parse_addr(args.next())?;
+                self.add_breakpoint(address);
+                Ok(format!("breakpoint set at {}\n", address))
+            }
+            "d" | "delete" => {
+                let address = parse_addr(args.next())?;
+                match self.remove_breakpoint(address) {
+                    true => Ok(format!("breakpoint at {} removed\n", address)),
+                    false => Err(format!("no breakpoint at {}", address)),
+                }
+            }
+            "w" | "watch" => {
+                let address = parse_addr(args.next())?;
+                self.add_watchpoint(address);
+                Ok(format!("watching {}\n", address))
+            }
+            "u" | "unwatch" => {
+                let address = parse_addr(args.next())?;
+                match self.remove_watchpoint(address) {
+                    true => Ok(format!("stopped watching {}\n", address)),
+                    false => Err(format!("not watching {}", address)),
+                }
+            }
+            "i" | "info" => {
+                let join = |set: &BTreeSet<usize>| set.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(" ");
+                Ok(format!("breakpoints: {}\nwatchpoints: {}\n", join(&self.breakpoints), join(&self.watchpoints)))
+            }
+            "r" | "regs" => Ok(self.regs()),
+            "l" | "list" => {
+                let address = match args.next() {
+                    Some(arg) => parse_addr(Some(arg))?,
+                    None => self.program.pc(),
+                };
+                let count = match args.next() {
+                    Some(n) => n.parse::<usize>().map_err(|_| format!("invalid count: {}", n))?,
+                    None => 10,
+                };
+                Ok(self.list(address, count))
+            }
+            "p" | "peek" => {
+                let address = parse_addr(args.next())?;
+                let count = match args.next() {
+                    Some(n) => n.parse::<usize>().map_err(|_| format!("invalid count: {}", n))?,
+                    None => 1,
+                };
+                let memory = self.program.memory();
+                // stop at the last address rather than wrapping
+                Ok((0..count)
+                    .map_while(|n| address.checked_add(n))
+                    .map(|a| format!("{}: {}\n", a, memory.get(a)))
+                    .collect())
+            }
+            "poke" => {
+                let address = parse_addr(args.next())?;
+                let value = match args.next() {
+                    Some(value) => parse_value(value)?,
+                    None => return Err(String::from("missing value")),
+                };
+                self.program.set_memory_at(address, value);
+                Ok(format!("{}: {}\n", address, value))
+            }
+            "who-wrote" => {
+                let address = parse_addr(args.next())?;
+                match self.program.history().and_then(|history| history.last_write_to(address)) {
+                    Some(undo) => {
+                        let (_, old) = undo.write.unwrap_or_default();
+                        Ok(format!(
+                            "{}: {} -> {} at step {} by\n{}\n",
+                            address,
+                            old,
+                            self.program.memory().get(address),
+                            undo.step,
+                            self.describe(undo.pc),
+                        ))
+                    }
+                    None => Ok(format!("no write to {} in the history\n", address)),
+                }
+            }
+            "in" => {
+                let values = args.map(parse_value).collect::<Result<Vec<_>, _>>()?;
+                for value in &values {
+                    self.program.push_input(*value);
+                }
+                Ok(format!("queued {} input values\n", values.len()))
+            }
+            "reset" => {
+                self.program.reset();
+                Ok(self.regs())
+            }
+            "h" | "help" => Ok(format!("{}\n", HELP)),
+            cmd => Err(format!("unknown command: {} (try help)", cmd)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod debug {
+        use super::super::*;
+
+        fn debugger(code: Vec<Value>) -> Debugger {
+            Debugger::new(Program::load(code))
+        }
+
+        #[test]
+        fn step_and_halt() {
+            let mut d = debugger(vec![1, 0, 0, 0, 99]);
+            assert_eq!(Stop::Stepped, d.step());
+            assert_eq!(4, d.program().pc());
+            assert_eq!(Stop::Halted, d.step());
+        }
+
+        #[test]
+        fn breakpoints() {
+            let mut d = debugger(vec![1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 99]);
+            d.add_breakpoint(4);
+            d.add_breakpoint(8);
+            assert_eq!(Stop::Breakpoint(4), d.cont());
+            assert_eq!(Stop::Breakpoint(8), d.cont());
+            assert!(d.remove_breakpoint(4));
+            assert!(!d.remove_breakpoint(4));
+            assert_eq!(Stop::Halted, d.cont());
+            assert_eq!(Some(8), d.program().memory_at(0));
+        }
+
+        #[test]
+        fn watchpoints() {
+            let mut d = debugger(vec![1101, 1, 1, 13, 1101, 2, 2, 14, 1101, 3, 3, 13, 99, 0, 0]);
+            d.add_watchpoint(13);
+            assert_eq!(Stop::Watchpoint { address: 13, pc: 0 }, d.cont());
+            assert_eq!(Stop::Watchpoint { address: 13, pc: 8 }, d.cont());
+            assert_eq!(Some(6), d.program().memory_at(13));
+            assert_eq!(Stop::Halted, d.cont());
+        }
+
+        #[test]
+        fn waiting_and_errors() {
+            let mut d = debugger(vec![3, 5, 4, 5, 42, 0]);
+            assert_eq!(Stop::WaitingForInput, d.cont());
+            d.program_mut().push_input(7);
+            assert_eq!(Stop::Error(Error::UnknownOpcode { pc: 4, instruction: 42 }), d.cont());
+            assert_eq!(vec![7], d.program_mut().take_output());
+        }
+
+        #[test]
+        fn reverse() {
+            let mut d = debugger(vec![1101, 1, 1, 13, 1101, 2, 2, 14, 1101, 3, 3, 13, 99, 0, 0]);
+            assert_eq!(Stop::Halted, d.cont());
+            d.add_watchpoint(13);
+            assert_eq!(Stop::Watchpoint { address: 13, pc: 8 }, d.reverse_cont());
+            assert_eq!(8, d.program().pc());
+            assert_eq!(Some(2), d.program().memory_at(13));
+            assert_eq!(Some(4), d.program().memory_at(14));
+            assert_eq!(Stop::Watchpoint { address: 13, pc: 0 }, d.reverse_cont());
+            assert_eq!(Some(0), d.program().memory_at(13));
+            assert_eq!(Stop::HistoryStart, d.reverse_cont());
+
+            // forwards again, then back to a breakpoint
+            d.remove_watchpoint(13);
+            assert_eq!(Stop::Halted, d.cont());
+            d.add_breakpoint(4);
+            assert_eq!(Stop::Breakpoint(4), d.reverse_cont());
+            assert_eq!(Some(0), d.program().memory_at(14));
+            assert_eq!(Stop::Stepped, d.reverse_step());
+            assert_eq!(Stop::HistoryStart, d.reverse_step());
+        }
+
+        #[test]
+        fn exec_reverse() {
+            let mut d = debugger(vec![1101, 1, 1, 13, 1101, 2, 2, 14, 1101, 3, 3, 13, 99, 0, 0]);
+            d.exec("c").unwrap();
+            let out = d.exec("who-wrote 13").unwrap();
+            assert!(out.starts_with("13: 2 -> 6 at step 2 by\nADD 3, 3, [13]"), "{}", out);
+            assert_eq!(Ok(String::from("no write to 0 in the history\n")), d.exec("who-wrote 0"));
+            // the halt counts as a step too
+            let out = d.exec("rs 2").unwrap();
+            assert!(out.starts_with("pc: 8"), "{}", out);
+            let out = d.exec("reverse-step 5").unwrap();
+            assert!(out.starts_with("reached the start of the history\npc: 0"), "{}", out);
+
+            // poking can't be undone, so it drops the history
+            d.exec("s 3").unwrap();
+            d.exec("poke 13 1").unwrap();
+            assert_eq!(Ok(String::from("no write to 13 in the history\n")), d.exec("who-wrote 13"));
+            let out = d.exec("rc").unwrap();
+            assert!(out.starts_with("reached the start of the history\npc: 12"), "{}", out);
+        }
+
+        #[test]
+        fn exec_commands() {
+            let mut d = debugger(vec![1, 0, 0, 0, 4, 0, 99]);
+            assert_eq!(Ok(String::from("breakpoint set at 4\n")), d.exec("b 4"));
+            assert_eq!(Ok(String::from("breakpoints: 4\nwatchpoints: \n")), d.exec("info"));
+            let out = d.exec("c").unwrap();
+            assert!(out.starts_with("breakpoint at 4\npc: 4  rb: 0\nOUT [0]"), "{}", out);
+            assert_eq!(Ok(String::from("0: 2\n1: 0\n")), d.exec("peek 0 2"));
+            assert_eq!(Ok(String::from("0: 9\n")), d.exec("poke 0 9"));
+            let out = d.exec("s").unwrap();
+            assert!(out.starts_with("output: 9\npc: 6"), "{}", out);
+            let out = d.exec("step").unwrap();
+            assert!(out.starts_with("halted\n"), "{}", out);
+            let out = d.exec("reset").unwrap();
+            assert!(out.starts_with("pc: 0"), "{}", out);
+            assert_eq!(Ok(String::new()), d.exec("  "));
+        }
+
+        #[test]
+        fn exec_list_and_input() {
+            let mut d = debugger(vec![3, 5, 99, 0, 0, 0]);
+            let out = d.exec("list 0 2").unwrap();
+            let lines: Vec<&str> = out.lines().collect();
+            assert_eq!(2, lines.len());
+            assert!(lines[0].starts_with("> IN [5]"), "{}", lines[0]);
+            assert!(lines[1].starts_with("  HLT"), "{}", lines[1]);
+            assert_eq!(Ok(String::from("queued 2 input values\n")), d.exec("in 1 2"));
+            d.exec("c").unwrap();
+            assert_eq!(Some(1), d.program().memory_at(5));
+        }
+
+        #[test]
+        fn exec_huge_addresses() {
+            let mut d = debugger(vec![99]);
+            let out = d.exec(&format!("list {}", usize::MAX)).unwrap();
+            assert_eq!(1, out.lines().count(), "{}", out);
+            assert!(out.contains(&format!("{}: 0", usize::MAX)), "{}", out);
+            let out = d.exec(&format!("list {} 5", usize::MAX - 2)).unwrap();
+            assert_eq!(3, out.lines().count(), "{}", out);
+            assert_eq!(Ok(format!("{}: 0\n", usize::MAX)), d.exec(&format!("peek {} 3", usize::MAX)));
+            assert!(d.exec(&format!("poke {} 8", usize::MAX)).is_ok());
+            assert_eq!(Ok(format!("{}: 8\n", usize::MAX)), d.exec(&format!("peek {}", usize::MAX)));
+        }
+
+        #[test]
+        fn exec_errors() {
+            let mut d = debugger(vec![99]);
+            assert_eq!(Err(String::from("missing address")), d.exec("b"));
+            assert_eq!(Err(String::from("invalid address: x")), d.exec("peek x"));
+            assert_eq!(Err(String::from("no breakpoint at 3")), d.exec("delete 3"));
+            assert_eq!(Err(String::from("invalid value: y")), d.exec("in 1 y"));
+            assert_eq!(Err(String::from("unknown command: jump (try help)")), d.exec("jump"));
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::Value;
+
+// Something mapped into a program's memory. Reads and writes through
+// position or relative parameters that land in the device's range go to it
+// instead of memory, with `offset` counted from the start of the range.
+// Instructions are always fetched from memory.
+pub trait Device {
+    fn read(&mut self, offset: usize) -> Value;
+    fn write(&mut self, offset: usize, value: Value);
+    // Called after every instruction the program executes.
+    fn tick(&mut self) {}
+}
+
+// Lets the caller keep a handle on a device after attaching it.
+impl<T: Device> Device for Rc<RefCell<T>> {
+    fn read(&mut self, offset: usize) -> Value {
+        self.borrow_mut().read(offset)
+    }
+
+    fn write(&mut self, offset: usize, value: Value) {
+        self.borrow_mut().write(offset, value)
+    }
+
+    fn tick(&mut self) {
+        self.borrow_mut().tick()
+    }
+}
+
+// Attaching a device over addresses another one already has.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Overlap {
+    pub existing: Range<usize>,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "overlaps the device at {}..{}", self.existing.start, self.existing.end)
+    }
+}
+
+impl error::Error for Overlap {}
+
+// Counts instructions executed. Reading gives the count, writing sets it.
+#[derive(Debug, Default)]
+pub struct Timer {
+    ticks: Value,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer::default()
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, _: usize) -> Value {
+        self.ticks
+    }
+
+    fn write(&mut self, _: usize, value: Value) {
+        self.ticks = value;
+    }
+
+    fn tick(&mut self) {
+        self.ticks = self.ticks.wrapping_add(1);
+    }
+}
+
+// Xorshift random numbers. Reading gives the next non-negative value,
+// writing reseeds.
+#[derive(Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck on zero
+        Rng { state: seed.max(1) }
+    }
+
+    pub fn next_value(&mut self) -> Value {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 1) as Value
+    }
+}
+
+impl Device for Rng {
+    fn read(&mut self, _: usize) -> Value {
+        self.next_value()
+    }
+
+    fn write(&mut self, _: usize, value: Value) {
+        *self = Rng::new(value as u64);
+    }
+}
+
+// A grid of pixels stored row by row.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: usize,
+    pixels: Vec<Value>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer { width, pixels: vec![0; width * height] }
+    }
+
+    // The number of addresses it needs.
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Value {
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: usize) -> Value {
+        self.pixels.get(offset).cloned().unwrap_or(0)
+    }
+
+    fn write(&mut self, offset: usize, value: Value) {
+        if let Some(pixel) = self.pixels.get_mut(offset) {
+            *pixel = value;
+        }
+    }
+}
+
+// Lit pixels as '#', one line per row.
+impl fmt::Display for Framebuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.pixels.chunks(self.width.max(1)) {
+            let line: String = row.iter().map(|p| if *p == 0 { ' ' } else { '#' }).collect();
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+// A single character port. Writes are collected as text, reads take the
+// next queued character or 0 when there isn't one.
+#[derive(Debug, Default)]
+pub struct Console {
+    input: VecDeque<Value>,
+    output: String,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console::default()
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.input.extend(text.bytes().map(Value::from));
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, _: usize) -> Value {
+        self.input.pop_front().unwrap_or(0)
+    }
+
+    fn write(&mut self, _: usize, value: Value) {
+        match value {
+            v if (0..128).contains(&v) => self.output.push(v as u8 as char),
+            // not ASCII, show the number like the ASCII front end does
+            v => self.output.push_str(&format!("[{}]", v)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod device {
+        use super::super::*;
+        use super::super::super::asm::assemble;
+        use super::super::super::budget::Budget;
+        use super::super::super::{fast, Error, Program, Status};
+
+        fn shared<T>(device: T) -> Rc<RefCell<T>> {
+            Rc::new(RefCell::new(device))
+        }
+
+        #[test]
+        fn attach_and_detach() {
+            let mut p = Program::load(vec![99]);
+            assert_eq!(Ok(()), p.attach(100..104, Timer::new()));
+            assert_eq!(Err(Overlap { existing: 100..104 }), p.attach(103..110, Timer::new()));
+            assert_eq!(Err(Overlap { existing: 100..104 }), p.attach(90..101, Timer::new()));
+            assert_eq!(Ok(()), p.attach(104..110, Timer::new()));
+            assert!(p.detach(102));
+            assert!(!p.detach(102));
+            assert_eq!(Ok(()), p.attach(90..101, Timer::new()));
+            assert_eq!("overlaps the device at 1..2", Overlap { existing: 1..2 }.to_string());
+        }
+
+        #[test]
+        fn timer() {
+            // copy the timer into 20 after a few instructions, then reset it
+            let code = assemble("
+                    ADD 0, 0, [20]
+                    ADD 0, 0, [20]
+                    ADD [100], 0, [20]
+                    ADD 5, 0, [100]
+                    HLT
+            ").unwrap().code;
+            let timer = shared(Timer::new());
+            let mut p = Program::load(code);
+            p.attach(100..101, timer.clone()).unwrap();
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(2, p.memory().get(20));
+            // reset to 5 on the fourth instruction, then ticked by it and
+            // the halt
+            assert_eq!(7, timer.borrow_mut().read(0));
+            // the memory under the device is untouched
+            assert_eq!(0, p.memory().get(100));
+        }
+
+        #[test]
+        fn rng() {
+            let mut a = Rng::new(42);
+            let mut b = Rng::new(42);
+            let values: Vec<Value> = (0..5).map(|_| a.next_value()).collect();
+            assert_eq!(values, (0..5).map(|_| b.next_value()).collect::<Vec<_>>());
+            assert!(values.iter().all(|v| *v >= 0));
+            assert_ne!(values[0], values[1]);
+            assert_ne!(Rng::new(0).next_value(), 0);
+
+            // reseeding through memory gives the same sequence again
+            let mut p = Program::load(vec![1101, 42, 0, 50, 4, 50, 4, 50, 99]);
+            p.attach(50..51, Rng::new(7)).unwrap();
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(values[..2].to_vec(), p.take_output());
+        }
+
+        #[test]
+        fn framebuffer() {
+            // a diagonal line through relative addressing
+            let code = assemble("
+                    ARB 1000
+            loop:   ADD 1, 0, [rb+0]
+                    ARB 4
+                    ADD [n], -1, [n]
+                    JT [n], loop
+                    HLT
+            n:      .data 3
+            ").unwrap().code;
+            let screen = shared(Framebuffer::new(3, 3));
+            let mut p = Program::load(code);
+            p.attach(1000..1009, screen.clone()).unwrap();
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(1, screen.borrow().pixel(2, 2));
+            assert_eq!(0, screen.borrow().pixel(1, 0));
+            assert_eq!("#\n #\n  #\n", screen.borrow().to_string());
+            assert_eq!(9, screen.borrow().len());
+        }
+
+        #[test]
+        fn console() {
+            // echo characters until a zero
+            let code = assemble("
+            loop:   ADD [200], 0, [c]
+                    JF [c], done
+                    ADD [c], 0, [200]
+                    JT 1, loop
+            done:   HLT
+            c:      .data 0
+            ").unwrap().code;
+            let console = shared(Console::new());
+            console.borrow_mut().push_str("hi!");
+            let mut p = Program::load(code);
+            p.attach(200..201, console.clone()).unwrap();
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!("hi!", console.borrow().output());
+
+            let mut console = Console::new();
+            console.write(0, 1000);
+            assert_eq!("[1000]", console.take_output());
+            assert_eq!("", console.output());
+        }
+
+        #[test]
+        fn polling_isnt_a_loop() {
+            // wait for the timer to pass 50
+            let code = assemble("
+            wait:   LT [300], 50, [t]
+                    JT [t], wait
+                    HLT
+            t:      .data 0
+            ").unwrap().code;
+            let mut p = Program::load(code.clone());
+            p.set_budget(Budget { detect_loops: true, ..Default::default() });
+            p.attach(300..301, Timer::new()).unwrap();
+            assert_eq!(Ok(Status::Halted), p.run());
+
+            // and the fast engine leaves devices to the interpreter
+            let mut p = Program::load(code);
+            p.attach(300..301, Timer::new()).unwrap();
+            assert_eq!(Ok(Status::Halted), fast::run(&mut p));
+            // 26 polls of two instructions, then the halt
+            assert_eq!(53, p.steps());
+        }
+
+        #[test]
+        fn bad_writes_still_fail() {
+            let mut p = Program::load(vec![1101, 1, 1, -1]);
+            p.attach(0..10, Timer::new()).unwrap();
+            assert_eq!(Err(Error::WriteOutOfBounds { pc: 0, instruction: 1101, address: -1 }), p.run());
+        }
+    }
+}
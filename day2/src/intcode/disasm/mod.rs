@@ -0,0 +1,186 @@
+use std::fmt;
+
+use super::{op_info, Instruction, Mode, Value};
+
+// The most values put on a single `.data` line.
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Operand {
+    pub mode: Mode,
+    pub value: Value,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "[rb-{}]", -(self.value as i128)),
+            Mode::Relative => write!(f, "[rb+{}]", self.value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Item {
+    Instruction { mnemonic: &'static str, operands: Vec<Operand> },
+    // Values that don't decode to a valid instruction.
+    Data(Vec<Value>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Line {
+    pub address: usize,
+    pub raw: Vec<Value>,
+    pub item: Item,
+}
+
+impl Line {
+    // The line as assembler source, without the address annotation.
+    pub fn source(&self) -> String {
+        match &self.item {
+            Item::Instruction { mnemonic, operands } if operands.is_empty() => mnemonic.to_string(),
+            Item::Instruction { mnemonic, operands } => format!(
+                "{} {}",
+                mnemonic,
+                operands.iter().map(|o| o.to_string()).collect::<Vec<_>>().join(", "),
+            ),
+            Item::Data(values) => format!(
+                ".data {}",
+                values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", "),
+            ),
+        }
+    }
+}
+
+// Lines print as assembler source with the address and raw values in a
+// trailing comment, so a listing can be fed back to the assembler.
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<32} ; {:>5}: {}",
+            self.source(),
+            self.address,
+            self.raw.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" "),
+        )
+    }
+}
+
+// Try to decode the instruction at `address`. Anything with an unknown
+// opcode, a bad mode, an immediate destination or operands running past the
+// end of memory isn't treated as code.
+pub fn decode_at(memory: &[Value], address: usize) -> Option<Line> {
+    let inst = Instruction::decode(address, memory[address]).ok()?;
+    let info = op_info(inst.op)?;
+    if address + info.params >= memory.len() {
+        return None;
+    }
+    if info.writes && inst.mode(info.params) == Mode::Immediate {
+        return None;
+    }
+
+    let operands = (1..=info.params)
+        .map(|param| Operand { mode: inst.mode(param), value: memory[address + param] })
+        .collect();
+
+    Some(Line {
+        address,
+        raw: memory[address..=address + info.params].to_vec(),
+        item: Item::Instruction { mnemonic: info.mnemonic, operands },
+    })
+}
+
+// Linear sweep over memory, decoding instructions where possible and
+// grouping everything else into data lines.
+pub fn disassemble(memory: &[Value]) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut address = 0;
+    while address < memory.len() {
+        match decode_at(memory, address) {
+            Some(line) => {
+                address += line.raw.len();
+                lines.push(line);
+            }
+            None => {
+                let value = memory[address];
+                match lines.last_mut() {
+                    Some(Line { raw, item: Item::Data(values), .. }) if values.len() < DATA_PER_LINE => {
+                        raw.push(value);
+                        values.push(value);
+                    }
+                    _ => lines.push(Line { address, raw: vec![value], item: Item::Data(vec![value]) }),
+                }
+                address += 1;
+            }
+        }
+    }
+    lines
+}
+
+pub fn listing(memory: &[Value]) -> String {
+    disassemble(memory)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    mod disasm {
+        use super::super::*;
+
+        #[test]
+        fn operand_display() {
+            assert_eq!("[5]", Operand { mode: Mode::Position, value: 5 }.to_string());
+            assert_eq!("-5", Operand { mode: Mode::Immediate, value: -5 }.to_string());
+            assert_eq!("[rb+5]", Operand { mode: Mode::Relative, value: 5 }.to_string());
+            assert_eq!("[rb-5]", Operand { mode: Mode::Relative, value: -5 }.to_string());
+        }
+
+        #[test]
+        fn instructions() {
+            let lines = disassemble(&[1002, 4, 3, 4, 204, -1, 99]);
+            assert_eq!(3, lines.len());
+            assert_eq!("MUL [4], 3, [4]", lines[0].source());
+            assert_eq!(0, lines[0].address);
+            assert_eq!(vec![1002, 4, 3, 4], lines[0].raw);
+            assert_eq!("OUT [rb-1]", lines[1].source());
+            assert_eq!(4, lines[1].address);
+            assert_eq!("HLT", lines[2].source());
+            assert_eq!(6, lines[2].address);
+        }
+
+        #[test]
+        fn data_regions() {
+            let lines = disassemble(&[99, 42, 10001, 0, 0, 0, 1, 2]);
+            assert_eq!(2, lines.len());
+            assert_eq!("HLT", lines[0].source());
+            assert_eq!(".data 42, 10001, 0, 0, 0, 1, 2", lines[1].source());
+            assert_eq!(1, lines[1].address);
+        }
+
+        #[test]
+        fn data_split_into_lines() {
+            let lines = disassemble(&[42; 10]);
+            assert_eq!(2, lines.len());
+            assert_eq!(8, lines[0].raw.len());
+            assert_eq!(8, lines[1].address);
+        }
+
+        #[test]
+        fn truncated_instruction() {
+            let lines = disassemble(&[1, 0, 0]);
+            assert_eq!(".data 1, 0, 0", lines[0].source());
+        }
+
+        #[test]
+        fn listing_annotations() {
+            assert_eq!(
+                format!("{:<32} ; {:>5}: 1 0 0 0\n{:<32} ; {:>5}: 99\n", "ADD [0], [0], [0]", 0, "HLT", 4),
+                listing(&[1, 0, 0, 0, 99]),
+            );
+        }
+    }
+}
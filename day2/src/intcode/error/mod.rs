@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+
+use super::Value;
+
+// Everything that can go wrong loading or running a program. Runtime errors
+// carry the pc and the raw instruction found there.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    UnknownOpcode { pc: usize, instruction: Value },
+    BadMode { pc: usize, instruction: Value, param: usize },
+    ReadOutOfBounds { pc: usize, instruction: Value, address: Value },
+    WriteOutOfBounds { pc: usize, instruction: Value, address: Value },
+    JumpOutOfBounds { pc: usize, instruction: Value, target: Value },
+    Overflow { pc: usize, instruction: Value, a: Value, b: Value },
+    InputExhausted { pc: usize, instruction: Value },
+    StepLimit { pc: usize, limit: u64 },
+    MemoryLimit { pc: usize, instruction: Value, address: usize },
+    // The machine came back to a state it was already in `period` steps ago.
+    Loop { pc: usize, period: u64 },
+    Parse { line: usize, column: usize, token: String },
+    Io(String),
+    // A line of input for an ASCII program held a character outside ASCII.
+    NotAscii(char),
+}
+
+impl Error {
+    // The pc the error happened at, if it happened while running.
+    pub fn pc(&self) -> Option<usize> {
+        match self {
+            Error::UnknownOpcode { pc, .. } |
+            Error::BadMode { pc, .. } |
+            Error::ReadOutOfBounds { pc, .. } |
+            Error::WriteOutOfBounds { pc, .. } |
+            Error::JumpOutOfBounds { pc, .. } |
+            Error::Overflow { pc, .. } |
+            Error::InputExhausted { pc, .. } |
+            Error::StepLimit { pc, .. } |
+            Error::MemoryLimit { pc, .. } |
+            Error::Loop { pc, .. } => Some(*pc),
+            Error::Parse { .. } | Error::Io(_) | Error::NotAscii(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode { pc, instruction } =>
+                write!(f, "unknown opcode in {} at pc {}", instruction, pc),
+            Error::BadMode { pc, instruction, param } =>
+                write!(f, "bad mode for parameter {} in {} at pc {}", param, instruction, pc),
+            Error::ReadOutOfBounds { pc, instruction, address } =>
+                write!(f, "read from address {} out of bounds in {} at pc {}", address, instruction, pc),
+            Error::WriteOutOfBounds { pc, instruction, address } =>
+                write!(f, "write to address {} out of bounds in {} at pc {}", address, instruction, pc),
+            Error::JumpOutOfBounds { pc, instruction, target } =>
+                write!(f, "jump to {} out of bounds in {} at pc {}", target, instruction, pc),
+            Error::Overflow { pc, instruction, a, b } =>
+                write!(f, "overflow combining {} and {} in {} at pc {}", a, b, instruction, pc),
+            Error::InputExhausted { pc, instruction } =>
+                write!(f, "input exhausted in {} at pc {}", instruction, pc),
+            Error::StepLimit { pc, limit } =>
+                write!(f, "step limit of {} reached at pc {}", limit, pc),
+            Error::MemoryLimit { pc, instruction, address } =>
+                write!(f, "write to address {} beyond memory limit in {} at pc {}", address, instruction, pc),
+            Error::Loop { pc, period } =>
+                write!(f, "infinite loop repeating every {} steps at pc {}", period, pc),
+            Error::Parse { line, column, token } =>
+                write!(f, "invalid value {:?} at line {}, column {}", token, line, column),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::NotAscii(c) => write!(f, "{:?} is not ASCII", c),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    mod error {
+        use super::super::*;
+
+        #[test]
+        fn display() {
+            let e = Error::UnknownOpcode { pc: 4, instruction: 42 };
+            assert_eq!("unknown opcode in 42 at pc 4", e.to_string());
+            let e = Error::Parse { line: 2, column: 3, token: String::from("x") };
+            assert_eq!("invalid value \"x\" at line 2, column 3", e.to_string());
+            let e = Error::Loop { pc: 2, period: 3 };
+            assert_eq!("infinite loop repeating every 3 steps at pc 2", e.to_string());
+            assert_eq!("'é' is not ASCII", Error::NotAscii('é').to_string());
+        }
+
+        #[test]
+        fn pc() {
+            assert_eq!(Some(8), Error::InputExhausted { pc: 8, instruction: 3 }.pc());
+            assert_eq!(None, Error::Io(String::from("missing")).pc());
+        }
+    }
+}
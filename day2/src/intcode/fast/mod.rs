@@ -0,0 +1,512 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use super::memory::Memory;
+use super::{io, op_info, Error, Instruction, Mode, Program, Status, Value};
+use super::{OP_ADD, OP_ARB, OP_EQ, OP_HLT, OP_IN, OP_JF, OP_JT, OP_LT, OP_MUL, OP_OUT};
+
+// Instructions at or above this aren't cached, so a jump to a huge address
+// doesn't grow the cache to match. Memory above it isn't flattened either.
+const CACHE_LIMIT: usize = 1 << 20;
+
+// An operand resolved when the instruction is decoded.
+#[derive(Clone, Copy)]
+enum Operand {
+    Immediate(Value),
+    Position(usize),
+    Relative(Value),
+    // A negative position, which is only an error if it's used.
+    Negative(Value),
+}
+
+type Handler = fn(&mut Engine, &Decoded) -> Result<Status, Error>;
+
+#[derive(Clone, Copy)]
+struct Decoded {
+    handler: Handler,
+    raw: Value,
+    operands: [Operand; 3],
+    len: usize,
+}
+
+// Works on the program's own memory, so a run that never loops costs nothing
+// to start or finish and only the cells written are touched.
+struct Engine {
+    memory: Memory,
+    // Once the program loops, the low part of memory is copied out here so
+    // reads and writes skip the page lookup. It goes back into `memory` when
+    // the run finishes.
+    flat: Vec<Value>,
+    // Decoded instructions by address, dropped when anything writes to one
+    // of their words. Until a jump goes backwards nothing can run twice, so
+    // straight-line code never pays for the cache.
+    cache: Vec<Option<Decoded>>,
+    code: Vec<bool>,
+    looped: bool,
+    pc: usize,
+    relative_base: Value,
+    steps: u64,
+    inputs: VecDeque<Value>,
+    outputs: VecDeque<Value>,
+    input: Option<Box<dyn io::Input>>,
+    output: Option<Box<dyn io::Output>>,
+}
+
+// Run `program` like `Program::run` does, decoding each instruction once and
+// dispatching straight to its handler afterwards. Programs that need watching
+// instruction by instruction are left to the interpreter.
+pub fn run(program: &mut Program) -> Result<Status, Error> {
+    if program.needs_interpreter() || program.halted {
+        return program.run();
+    }
+
+    let mut engine = Engine::new(program);
+    let result = engine.run();
+    engine.finish(program);
+    program.halted = result == Ok(Status::Halted);
+    result
+}
+
+impl Engine {
+    fn new(program: &mut Program) -> Engine {
+        Engine {
+            memory: mem::take(&mut program.memory),
+            flat: Vec::new(),
+            cache: Vec::new(),
+            code: Vec::new(),
+            looped: false,
+            pc: program.pc,
+            relative_base: program.relative_base,
+            steps: program.steps,
+            inputs: mem::take(&mut program.inputs),
+            outputs: mem::take(&mut program.outputs),
+            input: program.input.take(),
+            output: program.output.take(),
+        }
+    }
+
+    fn finish(mut self, program: &mut Program) {
+        // only cells that changed, so pages shared with snapshots stay shared
+        for (addr, value) in self.flat.iter().enumerate() {
+            if self.memory.get(addr) != *value {
+                self.memory.set(addr, *value);
+            }
+        }
+        program.memory = self.memory;
+        program.pc = self.pc;
+        program.relative_base = self.relative_base;
+        program.steps = self.steps;
+        program.last_write = None;
+        program.inputs = self.inputs;
+        program.outputs = self.outputs;
+        program.input = self.input;
+        program.output = self.output;
+    }
+
+    fn run(&mut self) -> Result<Status, Error> {
+        loop {
+            let decoded = match self.cache.get(self.pc) {
+                Some(Some(decoded)) => *decoded,
+                _ => self.decode()?,
+            };
+            match (decoded.handler)(self, &decoded)? {
+                Status::Running => self.steps += 1,
+                Status::Halted => {
+                    self.steps += 1;
+                    return Ok(Status::Halted);
+                }
+                Status::WaitingForInput => return Ok(Status::WaitingForInput),
+            }
+        }
+    }
+
+    fn get(&self, addr: usize) -> Value {
+        match self.flat.get(addr) {
+            Some(value) => *value,
+            None => self.memory.get(addr),
+        }
+    }
+
+    fn store(&mut self, addr: usize, value: Value) {
+        match self.flat.get_mut(addr) {
+            Some(cell) => *cell = value,
+            None => self.memory.set(addr, value),
+        }
+        if self.code.get(addr).cloned().unwrap_or(false) {
+            self.invalidate(addr);
+        }
+    }
+
+    // Drop every cached instruction covering `addr`.
+    fn invalidate(&mut self, addr: usize) {
+        for start in addr.saturating_sub(3)..=addr {
+            if let Some(Some(decoded)) = self.cache.get(start) {
+                if start + decoded.len > addr {
+                    self.cache[start] = None;
+                }
+            }
+        }
+    }
+
+    fn decode(&mut self) -> Result<Decoded, Error> {
+        let pc = self.pc;
+        let inst = Instruction::decode(pc, self.get(pc))?;
+        let info = match op_info(inst.op) {
+            Some(info) => info,
+            None => return Err(Error::UnknownOpcode { pc, instruction: inst.raw }),
+        };
+        let handler: Handler = match inst.op {
+            OP_ADD => add,
+            OP_MUL => mul,
+            OP_IN => input,
+            OP_OUT => output,
+            OP_JT => jump_true,
+            OP_JF => jump_false,
+            OP_LT => less_than,
+            OP_EQ => equals,
+            OP_ARB => adjust_base,
+            OP_HLT => halt,
+            _ => return Err(Error::UnknownOpcode { pc, instruction: inst.raw }),
+        };
+
+        let mut operands = [Operand::Immediate(0); 3];
+        for (idx, operand) in operands.iter_mut().take(info.params).enumerate() {
+            let value = self.get(pc + idx + 1);
+            *operand = match inst.mode(idx + 1) {
+                Mode::Immediate => Operand::Immediate(value),
+                Mode::Relative => Operand::Relative(value),
+                Mode::Position => match value {
+                    v if v < 0 => Operand::Negative(v),
+                    v => Operand::Position(v as usize),
+                },
+            };
+        }
+
+        let decoded = Decoded { handler, raw: inst.raw, operands, len: info.params + 1 };
+        if pc + decoded.len <= CACHE_LIMIT && self.looped {
+            if pc >= self.cache.len() {
+                self.cache.resize(pc + 1, None);
+            }
+            if pc + decoded.len > self.code.len() {
+                self.code.resize(pc + decoded.len, false);
+            }
+            self.cache[pc] = Some(decoded);
+            for covered in &mut self.code[pc..pc + decoded.len] {
+                *covered = true;
+            }
+        }
+        Ok(decoded)
+    }
+
+    fn relative(&self, d: &Decoded, offset: Value) -> Result<Value, Error> {
+        match self.relative_base.checked_add(offset) {
+            Some(addr) => Ok(addr),
+            None => Err(Error::Overflow { pc: self.pc, instruction: d.raw, a: self.relative_base, b: offset }),
+        }
+    }
+
+    fn read(&self, d: &Decoded, param: usize) -> Result<Value, Error> {
+        let addr = match d.operands[param - 1] {
+            Operand::Immediate(value) => return Ok(value),
+            Operand::Position(addr) => return Ok(self.get(addr)),
+            Operand::Relative(offset) => self.relative(d, offset)?,
+            Operand::Negative(addr) => addr,
+        };
+        match addr {
+            a if a < 0 => Err(Error::ReadOutOfBounds { pc: self.pc, instruction: d.raw, address: a }),
+            a => Ok(self.get(a as usize)),
+        }
+    }
+
+    fn write_addr(&self, d: &Decoded, param: usize) -> Result<usize, Error> {
+        let addr = match d.operands[param - 1] {
+            Operand::Immediate(_) => return Err(Error::BadMode { pc: self.pc, instruction: d.raw, param }),
+            Operand::Position(addr) => return Ok(addr),
+            Operand::Relative(offset) => self.relative(d, offset)?,
+            Operand::Negative(addr) => addr,
+        };
+        match addr {
+            a if a < 0 => Err(Error::WriteOutOfBounds { pc: self.pc, instruction: d.raw, address: a }),
+            a => Ok(a as usize),
+        }
+    }
+
+    fn write(&mut self, d: &Decoded, param: usize, value: Value) -> Result<(), Error> {
+        let addr = self.write_addr(d, param)?;
+        self.store(addr, value);
+        Ok(())
+    }
+
+    fn flatten(&mut self) {
+        let len = self.memory.dense_len().min(CACHE_LIMIT);
+        self.flat = (0..len).map(|addr| self.memory.get(addr)).collect();
+    }
+
+    // Shared by the instructions that combine two parameters into a third.
+    fn binary(&mut self, d: &Decoded, f: fn(Value, Value) -> Option<Value>) -> Result<Status, Error> {
+        let a = self.read(d, 1)?;
+        let b = self.read(d, 2)?;
+        let result = match f(a, b) {
+            Some(result) => result,
+            None => return Err(Error::Overflow { pc: self.pc, instruction: d.raw, a, b }),
+        };
+        self.write(d, 3, result)?;
+        self.pc += 4;
+        Ok(Status::Running)
+    }
+
+    fn jump(&mut self, d: &Decoded, when: bool) -> Result<Status, Error> {
+        let cond = self.read(d, 1)?;
+        let target = self.read(d, 2)?;
+        match target {
+            _ if (cond != 0) != when => self.pc += 3,
+            t if t < 0 => return Err(Error::JumpOutOfBounds { pc: self.pc, instruction: d.raw, target: t }),
+            t => {
+                if t as usize <= self.pc && !self.looped {
+                    self.looped = true;
+                    self.flatten();
+                }
+                self.pc = t as usize;
+            }
+        }
+        Ok(Status::Running)
+    }
+}
+
+fn add(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    e.binary(d, Value::checked_add)
+}
+
+fn mul(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    e.binary(d, Value::checked_mul)
+}
+
+fn less_than(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    e.binary(d, |a, b| Some((a < b) as Value))
+}
+
+fn equals(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    e.binary(d, |a, b| Some((a == b) as Value))
+}
+
+fn jump_true(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    e.jump(d, true)
+}
+
+fn jump_false(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    e.jump(d, false)
+}
+
+fn input(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    let addr = e.write_addr(d, 1)?;
+    let value = match e.inputs.pop_front() {
+        Some(value) => value,
+        None => match &mut e.input {
+            Some(input) => match input.read() {
+                Some(value) => value,
+                None if input.exhausted() => return Err(Error::InputExhausted { pc: e.pc, instruction: d.raw }),
+                None => return Ok(Status::WaitingForInput),
+            }
+            None => return Ok(Status::WaitingForInput),
+        }
+    };
+    e.store(addr, value);
+    e.pc += 2;
+    Ok(Status::Running)
+}
+
+fn output(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    let value = e.read(d, 1)?;
+    match &mut e.output {
+        Some(output) => output.write(value),
+        None => e.outputs.push_back(value),
+    }
+    e.pc += 2;
+    Ok(Status::Running)
+}
+
+fn adjust_base(e: &mut Engine, d: &Decoded) -> Result<Status, Error> {
+    let offset = e.read(d, 1)?;
+    e.relative_base = match e.relative_base.checked_add(offset) {
+        Some(base) => base,
+        None => return Err(Error::Overflow { pc: e.pc, instruction: d.raw, a: e.relative_base, b: offset }),
+    };
+    e.pc += 2;
+    Ok(Status::Running)
+}
+
+fn halt(_: &mut Engine, _: &Decoded) -> Result<Status, Error> {
+    Ok(Status::Halted)
+}
+
+#[cfg(test)]
+mod tests {
+    mod fast {
+        use super::super::*;
+        use super::super::super::asm::assemble;
+        use super::super::super::testing;
+
+        // Counts [n] down to zero, outputting every value on the way. The
+        // instruction at `count` has its operand rewritten every time round
+        // the loop so the cache has to keep up.
+        const COUNTDOWN: &str = "
+                    IN [n]
+            loop:   OUT [n]
+                    ADD [n], -1, [n]
+                    ADD [count+1], 1, [count+1]
+            count:  ADD 0, 0, [total]
+                    JT [n], loop
+                    HLT
+            n:      .data 0
+            total:  .data 0
+        ";
+
+        fn differential(code: Vec<Value>, inputs: &[Value]) -> Program {
+            testing::differential(code, inputs, run).0
+        }
+
+        #[test]
+        fn day2() {
+            let code = Program::load_from_file("data/input.txt").unwrap().memory().to_vec();
+            for (noun, verb) in [(12, 2), (71, 95), (0, 0)] {
+                let mut code = code.clone();
+                code[1] = noun;
+                code[2] = verb;
+                differential(code, &[]);
+            }
+        }
+
+        #[test]
+        fn io_and_relative() {
+            let compare = vec![
+                3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21,
+                125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99,
+            ];
+            for input in [7, 8, 9] {
+                differential(compare.clone(), &[input]);
+            }
+            differential(vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99], &[]);
+            differential(vec![104, 1125899906842624, 99], &[]);
+        }
+
+        #[test]
+        fn self_modifying() {
+            let code = assemble(COUNTDOWN).unwrap().code;
+            differential(code, &[5]);
+            // an instruction overwritten with a halt
+            differential(vec![109, 6, 21101, 0, 99, 0, 1, 0, 0, 0], &[]);
+        }
+
+        #[test]
+        fn waiting_for_input() {
+            let code = assemble(COUNTDOWN).unwrap().code;
+            let mut interpreted = Program::load(code.clone());
+            let mut program = Program::load(code);
+            assert_eq!(Ok(Status::WaitingForInput), run(&mut program));
+            assert_eq!(interpreted.run(), Ok(Status::WaitingForInput));
+            assert_eq!(interpreted.steps(), program.steps());
+            program.push_input(3);
+            assert_eq!(Ok(Status::Halted), run(&mut program));
+            assert_eq!(vec![3, 2, 1], program.take_output());
+
+            // sources and sinks are used and handed back
+            let mut program = Program::load(vec![3, 0, 4, 0, 99]);
+            program.set_input(io::FromIter::new(vec![42]));
+            let outputs = io::Queue::new();
+            program.set_output(outputs.clone());
+            assert_eq!(Ok(Status::Halted), run(&mut program));
+            assert_eq!(Some(42), outputs.pop());
+            program.reset();
+            assert_eq!(Err(Error::InputExhausted { pc: 0, instruction: 3 }), run(&mut program));
+        }
+
+        #[test]
+        fn errors() {
+            differential(vec![1, -1, 0, 0, 99], &[]);
+            differential(vec![1, 0, 0, -1, 99], &[]);
+            differential(vec![22201, 0, 0, 0, 99], &[]);
+            differential(vec![109, -1, 22201, 0, 0, 0, 99], &[]);
+            differential(vec![109, Value::MAX, 209, 1, 99], &[]);
+            differential(vec![1101, Value::MAX, 1, 0, 99], &[]);
+            differential(vec![11101, 1, 1, 0, 99], &[]);
+            differential(vec![1105, 1, -1], &[]);
+            differential(vec![42], &[]);
+            differential(vec![40001], &[]);
+            differential(vec![-1], &[]);
+        }
+
+        #[test]
+        fn large_addresses() {
+            let program = differential(vec![1101, 1, 2, 1 << 30, 1001, 1 << 30, 1, 5, 99], &[]);
+            assert_eq!(3, program.memory().get(1 << 30));
+            // running off the end of memory reads zeros, which decode as an
+            // unknown opcode
+            differential(vec![1101, 0, 0, 6], &[]);
+
+            // memory is used where it is, so a long one isn't copied
+            let mut program = Program::load(vec![1101, 1, 2, 5, 99]);
+            program.set_memory_at(1 << 40, 7);
+            assert_eq!(Ok(Status::Halted), run(&mut program));
+            assert_eq!(2, program.memory().pages());
+            assert_eq!((1 << 40) + 1, program.memory().len());
+        }
+
+        #[test]
+        fn tracing_uses_the_interpreter() {
+            let mut program = Program::load(vec![1101, 1, 2, 0, 99]);
+            let profile = std::rc::Rc::new(std::cell::RefCell::new(super::super::super::trace::Profile::new()));
+            program.set_tracer(profile.clone());
+            assert_eq!(Ok(Status::Halted), run(&mut program));
+            assert_eq!(2, profile.borrow().steps());
+        }
+
+        #[test]
+        fn flattened_memory_goes_back() {
+            let code = assemble(COUNTDOWN).unwrap().code;
+            let mut program = Program::load(code.clone());
+            program.set_memory_at(1 << 40, 7);
+            program.push_input(3);
+            let before = program.snapshot();
+            assert_eq!(Ok(Status::Halted), run(&mut program));
+            assert_eq!(7, program.memory().get(1 << 40));
+            assert_eq!(2, program.memory().pages());
+            // the snapshot's pages weren't written through
+            let start = |memory: &Memory| (0..code.len()).map(|addr| memory.get(addr)).collect::<Vec<_>>();
+            assert_eq!(code, start(&before.memory));
+            assert_ne!(code, start(program.memory()));
+        }
+
+        #[test]
+        fn faster_on_loops() {
+            // counts down from its input
+            let code = assemble("
+                        IN [n]
+                loop:   ADD [n], -1, [n]
+                        MUL [n], 3, [x]
+                        JT [n], loop
+                        HLT
+                n:      .data 0
+                x:      .data 0
+            ").unwrap().code;
+            type Engine = fn(&mut Program) -> Result<Status, Error>;
+            let engines: [Engine; 2] = [Program::run, run];
+            // best of a few rounds, so a busy machine doesn't decide it
+            let times: Vec<_> = engines
+                .iter()
+                .map(|engine| {
+                    (0..3)
+                        .map(|_| {
+                            let mut program = Program::load(code.clone());
+                            program.push_input(20_000);
+                            let start = std::time::Instant::now();
+                            assert_eq!(Ok(Status::Halted), engine(&mut program));
+                            start.elapsed()
+                        })
+                        .min()
+                        .unwrap()
+                })
+                .collect();
+            assert!(times[1] < times[0], "interpreter {:?}, fast {:?}", times[0], times[1]);
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use std::mem::{self, Discriminant};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use super::budget::Budget;
+use super::device::Rng;
+use super::trace::{Event, Tracer};
+use super::{Error, Program, Status, Value};
+
+const DEFAULT_STEPS: u64 = 100_000;
+const DEFAULT_MEMORY: usize = 1 << 20;
+const DEFAULT_VALUES: RangeInclusive<Value> = -1000..=1000;
+const DEFAULT_MAX_INPUT: usize = 64;
+
+// Tried now and then whatever the value range, since comparisons against
+// them are common.
+const INTERESTING: [Value; 6] = [0, 1, -1, 2, 10, 99];
+
+// One run's worth of fuzzed data: the input stream and a value for each cell
+// being fuzzed, in the order the cells were added.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Hash)]
+pub struct Case {
+    pub input: Vec<Value>,
+    pub cells: Vec<Value>,
+}
+
+// A case that makes the program fail, shrunk as far as it would go while
+// still failing the same way.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Crash {
+    pub case: Case,
+    pub error: Error,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Report {
+    // Cases that each reached an address none before them did.
+    pub corpus: Vec<Case>,
+    // Every address executed by any case.
+    pub coverage: BTreeSet<usize>,
+    // One per kind of error and pc.
+    pub crashes: Vec<Crash>,
+    pub executions: u64,
+}
+
+#[derive(Default)]
+struct Coverage(HashSet<usize>);
+
+impl Tracer for Coverage {
+    fn trace(&mut self, event: &Event) {
+        self.0.insert(event.pc);
+    }
+}
+
+// Running out of budget means the case was slow or stuck, not that the
+// program is broken.
+fn is_crash(error: &Error) -> bool {
+    !matches!(
+        error,
+        Error::StepLimit { .. } | Error::MemoryLimit { .. } | Error::Loop { .. } | Error::InputExhausted { .. }
+    )
+}
+
+type CrashKey = (Option<usize>, Discriminant<Error>);
+
+fn crash_key(error: &Error) -> CrashKey {
+    (error.pc(), mem::discriminant(error))
+}
+
+fn pick(rng: &mut Rng, range: &RangeInclusive<Value>) -> Value {
+    let span = range.end().wrapping_sub(*range.start()) as u64;
+    match span.checked_add(1) {
+        Some(size) => range.start().wrapping_add((rng.next_value() as u64 % size) as Value),
+        None => rng.next_value(),
+    }
+}
+
+fn below(rng: &mut Rng, n: usize) -> usize {
+    rng.next_value() as usize % n.max(1)
+}
+
+// Smaller versions of `value` for minimizing, closest to zero first.
+fn simpler(value: Value) -> [Value; 3] {
+    [0, value / 2, value - value.signum()]
+}
+
+// Feeds a program mutated input streams, keeping the ones that reach new
+// code and reporting the ones that crash it. The same seed always gives the
+// same report.
+#[derive(Debug, Clone)]
+pub struct Fuzzer {
+    code: Vec<Value>,
+    seed: u64,
+    cells: Vec<(usize, RangeInclusive<Value>)>,
+    values: RangeInclusive<Value>,
+    max_input: usize,
+    seeds: Vec<Vec<Value>>,
+    budget: Budget,
+}
+
+impl Fuzzer {
+    pub fn new(code: Vec<Value>) -> Fuzzer {
+        Fuzzer {
+            code,
+            seed: 1,
+            cells: Vec::new(),
+            values: DEFAULT_VALUES,
+            max_input: DEFAULT_MAX_INPUT,
+            seeds: Vec::new(),
+            budget: Budget { steps: Some(DEFAULT_STEPS), memory: Some(DEFAULT_MEMORY), detect_loops: false },
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Fuzzer {
+        self.seed = seed;
+        self
+    }
+
+    // Also fuzz the initial value of the cell at `address` within `range`.
+    pub fn cell(mut self, address: usize, range: RangeInclusive<Value>) -> Fuzzer {
+        self.cells.push((address, range));
+        self
+    }
+
+    // Draw random input values from `range`.
+    pub fn values(mut self, range: RangeInclusive<Value>) -> Fuzzer {
+        self.values = range;
+        self
+    }
+
+    pub fn max_input(mut self, len: usize) -> Fuzzer {
+        self.max_input = len;
+        self
+    }
+
+    // Start the corpus from `input` rather than from nothing.
+    pub fn input(mut self, input: Vec<Value>) -> Fuzzer {
+        self.seeds.push(input);
+        self
+    }
+
+    // Run each case on `budget`. Cases that exceed it aren't crashes.
+    pub fn budget(mut self, budget: Budget) -> Fuzzer {
+        self.budget = budget;
+        self
+    }
+
+    pub fn run(&self, iterations: u64) -> Report {
+        let mut rng = Rng::new(self.seed);
+        let mut program = Program::load(self.code.clone());
+        program.set_budget(self.budget);
+        let coverage = Rc::new(RefCell::new(Coverage::default()));
+        program.set_tracer(coverage.clone());
+
+        let mut report = Report::default();
+        let mut crashes = HashSet::new();
+        let cells: Vec<Value> = self.cells.iter().map(|(_, range)| *range.start()).collect();
+        let mut initial: Vec<Case> =
+            self.seeds.iter().map(|input| Case { input: input.clone(), cells: cells.clone() }).collect();
+        if initial.is_empty() {
+            initial.push(Case { input: Vec::new(), cells });
+        }
+
+        for case in &initial {
+            self.try_case(&mut program, &coverage, case, &mut report, &mut crashes);
+        }
+        for _ in 0..iterations {
+            // everything so far may have crashed, leaving nothing to mutate
+            let parent = match report.corpus.len() {
+                0 => &initial[below(&mut rng, initial.len())],
+                len => &report.corpus[below(&mut rng, len)],
+            };
+            let case = self.mutate(parent, &mut rng);
+            self.try_case(&mut program, &coverage, &case, &mut report, &mut crashes);
+        }
+        report
+    }
+
+    fn execute(&self, program: &mut Program, case: &Case) -> Result<Status, Error> {
+        program.reset();
+        for ((address, _), value) in self.cells.iter().zip(&case.cells) {
+            program.set_memory_at(*address, *value);
+        }
+        for value in &case.input {
+            program.push_input(*value);
+        }
+        // running out of input just ends the case
+        program.run()
+    }
+
+    fn try_case(
+        &self,
+        program: &mut Program,
+        coverage: &Rc<RefCell<Coverage>>,
+        case: &Case,
+        report: &mut Report,
+        crashes: &mut HashSet<CrashKey>,
+    ) {
+        coverage.borrow_mut().0.clear();
+        let result = self.execute(program, case);
+        report.executions += 1;
+
+        let mut new = false;
+        for pc in coverage.borrow().0.iter() {
+            new |= report.coverage.insert(*pc);
+        }
+        match result {
+            Err(error) if is_crash(&error) => {
+                let key = crash_key(&error);
+                if crashes.insert(key) {
+                    let crash = self.minimize(program, Crash { case: case.clone(), error }, &mut report.executions);
+                    report.crashes.push(crash);
+                }
+            }
+            _ if new => report.corpus.push(case.clone()),
+            _ => (),
+        }
+    }
+
+    fn mutate(&self, parent: &Case, rng: &mut Rng) -> Case {
+        let mut case = parent.clone();
+        for _ in 0..1 + below(rng, 4) {
+            let len = case.input.len();
+            match below(rng, 6) {
+                0 if len > 0 => {
+                    let idx = below(rng, len);
+                    case.input[idx] = pick(rng, &self.values);
+                }
+                1 if len > 0 => {
+                    let idx = below(rng, len);
+                    case.input[idx] = INTERESTING[below(rng, INTERESTING.len())];
+                }
+                2 if len > 0 => {
+                    let idx = below(rng, len);
+                    let delta = 1 + below(rng, 16) as Value;
+                    case.input[idx] = match rng.next_value() % 2 {
+                        0 => case.input[idx].saturating_add(delta),
+                        _ => case.input[idx].saturating_sub(delta),
+                    };
+                }
+                3 if len > 0 => {
+                    case.input.remove(below(rng, len));
+                }
+                4 if !self.cells.is_empty() => {
+                    let idx = below(rng, self.cells.len());
+                    case.cells[idx] = pick(rng, &self.cells[idx].1);
+                }
+                _ if len < self.max_input => {
+                    let value = pick(rng, &self.values);
+                    case.input.insert(below(rng, len + 1), value);
+                }
+                _ => (),
+            }
+        }
+        case
+    }
+
+    // Drop input values and move values towards zero for as long as the
+    // program still fails the same way.
+    fn minimize(&self, program: &mut Program, crash: Crash, executions: &mut u64) -> Crash {
+        let key = crash_key(&crash.error);
+        let mut crashes = |candidate: &Case| {
+            *executions += 1;
+            match self.execute(program, candidate) {
+                Err(e) if crash_key(&e) == key => Some(e),
+                _ => None,
+            }
+        };
+
+        let Crash { mut case, mut error } = crash;
+        let mut shrunk = true;
+        while shrunk {
+            shrunk = false;
+            let mut idx = 0;
+            while idx < case.input.len() {
+                let mut candidate = case.clone();
+                candidate.input.remove(idx);
+                match crashes(&candidate) {
+                    Some(e) => {
+                        case = candidate;
+                        error = e;
+                        shrunk = true;
+                    }
+                    None => idx += 1,
+                }
+            }
+            for idx in 0..case.input.len() {
+                for value in simpler(case.input[idx]).iter().filter(|v| **v != case.input[idx]) {
+                    let mut candidate = case.clone();
+                    candidate.input[idx] = *value;
+                    if let Some(e) = crashes(&candidate) {
+                        case = candidate;
+                        error = e;
+                        shrunk = true;
+                        break;
+                    }
+                }
+            }
+            for idx in 0..case.cells.len() {
+                let range = &self.cells[idx].1;
+                for value in simpler(case.cells[idx]).iter().filter(|v| **v != case.cells[idx] && range.contains(v)) {
+                    let mut candidate = case.clone();
+                    candidate.cells[idx] = *value;
+                    if let Some(e) = crashes(&candidate) {
+                        case = candidate;
+                        error = e;
+                        shrunk = true;
+                        break;
+                    }
+                }
+            }
+        }
+        Crash { case, error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod fuzz {
+        use super::super::*;
+        use super::super::super::asm::assemble;
+
+        // crashes on an input over 10 followed by a zero
+        const GUARDED: &str = "
+                IN [a]
+                LT 10, [a], [t]
+                JF [t], done
+                IN [b]
+                JT [b], done
+                .data 42
+        done:   HLT
+        a:      .data 0
+        b:      .data 0
+        t:      .data 0
+        ";
+
+        #[test]
+        fn finds_and_minimizes_crashes() {
+            let assembly = assemble(GUARDED).unwrap();
+            let report = Fuzzer::new(assembly.code.clone()).seed(7).run(5000);
+            assert_eq!(1, report.crashes.len());
+            let crash = &report.crashes[0];
+            let pc = assembly.labels["done"] as usize - 1;
+            assert_eq!(Error::UnknownOpcode { pc, instruction: 42 }, crash.error);
+            assert_eq!(vec![11, 0], crash.case.input);
+
+            // the reproducer crashes on its own
+            let mut p = Program::load(assembly.code);
+            p.push_input(11);
+            p.push_input(0);
+            assert_eq!(Err(crash.error.clone()), p.run());
+        }
+
+        #[test]
+        fn corpus_and_coverage() {
+            let assembly = assemble(GUARDED).unwrap();
+            let fuzzer = Fuzzer::new(assembly.code).seed(3);
+            let report = fuzzer.run(500);
+            // minimizing a crash takes extra runs
+            assert!(report.executions >= 501);
+            // the empty case runs nothing, so the corpus starts with the
+            // first case to read a value
+            assert!(report.corpus.len() >= 2);
+            assert_eq!(1, report.corpus[0].input.len());
+            assert!(report.coverage.contains(&0));
+            assert!(report.coverage.contains(&(assembly.labels["done"] as usize)));
+            assert_eq!(report, fuzzer.run(500));
+            assert_ne!(report, fuzzer.clone().seed(4).run(500));
+        }
+
+        #[test]
+        fn cells() {
+            // read through the cell at 1, which fails when it's negative
+            let report = Fuzzer::new(vec![1, 0, 0, 5, 99, 0]).cell(1, -5..=5).max_input(0).run(50);
+            assert_eq!(1, report.crashes.len());
+            assert_eq!(Error::ReadOutOfBounds { pc: 0, instruction: 1, address: -1 }, report.crashes[0].error);
+            assert_eq!(Case { input: Vec::new(), cells: vec![-1] }, report.crashes[0].case);
+        }
+
+        #[test]
+        fn hangs_arent_crashes() {
+            // spins forever on input 5
+            let code = assemble("
+            loop:   IN [a]
+                    EQ [a], 5, [t]
+            spin:   JT [t], spin
+                    HLT
+            a:      .data 0
+            t:      .data 0
+            ").unwrap().code;
+            let report = Fuzzer::new(code)
+                .values(0..=9)
+                .input(vec![5])
+                .budget(Budget { steps: Some(100), ..Default::default() })
+                .run(100);
+            assert!(report.crashes.is_empty());
+            assert!(!report.corpus.is_empty());
+            assert!(!is_crash(&Error::StepLimit { pc: 0, limit: 1 }));
+            assert!(is_crash(&Error::JumpOutOfBounds { pc: 0, instruction: 5, target: -1 }));
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use super::Value;
+
+// What it takes to undo one executed instruction.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Undo {
+    // The step the instruction ran as, and the registers before it ran.
+    pub step: u64,
+    pub pc: usize,
+    pub relative_base: Value,
+    // The address written and the value it held before.
+    pub write: Option<(usize, Value)>,
+    // Input consumed, which goes back on the front of the input queue.
+    pub input: Option<Value>,
+    pub output: Option<Value>,
+}
+
+// The undo log for the most recent `limit` instructions. `Program` fills in
+// an entry while an instruction runs and keeps it once the instruction has
+// finished.
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: VecDeque<Undo>,
+    limit: usize,
+    pending: Option<Undo>,
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History { entries: VecDeque::new(), limit, pending: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pending = None;
+    }
+
+    // Oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Undo> {
+        self.entries.iter()
+    }
+
+    // The most recent write to `address` still in the log.
+    pub fn last_write_to(&self, address: usize) -> Option<&Undo> {
+        self.entries.iter().rev().find(|undo| matches!(undo.write, Some((a, _)) if a == address))
+    }
+
+    pub fn begin(&mut self, step: u64, pc: usize, relative_base: Value) {
+        self.pending = Some(Undo { step, pc, relative_base, write: None, input: None, output: None });
+    }
+
+    pub fn wrote(&mut self, address: usize, old: Value) {
+        if let Some(undo) = &mut self.pending {
+            undo.write = Some((address, old));
+        }
+    }
+
+    pub fn consumed(&mut self, value: Value) {
+        if let Some(undo) = &mut self.pending {
+            undo.input = Some(value);
+        }
+    }
+
+    pub fn produced(&mut self, value: Value) {
+        if let Some(undo) = &mut self.pending {
+            undo.output = Some(value);
+        }
+    }
+
+    pub fn commit(&mut self) {
+        if let Some(undo) = self.pending.take() {
+            if self.entries.len() == self.limit {
+                self.entries.pop_front();
+            }
+            if self.limit > 0 {
+                self.entries.push_back(undo);
+            }
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Undo> {
+        self.entries.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod history {
+        use super::super::*;
+        use super::super::super::{fast, Program, Status};
+
+        #[test]
+        fn limit() {
+            let mut history = History::new(2);
+            for step in 0..3 {
+                history.begin(step, step as usize, 0);
+                history.wrote(5, step as Value);
+                history.commit();
+            }
+            assert_eq!(2, history.len());
+            assert_eq!(Some(1), history.iter().next().map(|undo| undo.step));
+            assert_eq!(Some(2), history.last_write_to(5).map(|undo| undo.step));
+            assert_eq!(None, history.last_write_to(6));
+            // nothing pending to commit
+            history.commit();
+            assert_eq!(2, history.len());
+
+            let mut history = History::new(0);
+            history.begin(0, 0, 0);
+            history.commit();
+            assert!(history.is_empty());
+        }
+
+        #[test]
+        fn step_back_to_the_start() {
+            // double the input, add 3 to the base and output
+            let code = vec![3, 13, 1002, 13, 2, 13, 109, 3, 4, 13, 99, 0, 0, 0];
+            let mut p = Program::load(code.clone());
+            p.enable_history(100);
+            p.push_input(21);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(42, p.memory().get(13));
+            assert_eq!(3, p.relative_base());
+            assert_eq!(Some(5), p.history().map(|h| h.len()));
+
+            // the output is still buffered, so undoing OUT takes it back
+            while p.step_back() {}
+            assert_eq!(0, p.pc());
+            assert_eq!(0, p.relative_base());
+            assert_eq!(0, p.steps());
+            assert_eq!(Program::load(code).memory().nonzero(), p.memory().nonzero());
+            assert!(p.take_output().is_empty());
+
+            // and running again reads the same input
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![42], p.take_output());
+        }
+
+        #[test]
+        fn halting_is_recorded_once() {
+            let mut p = Program::load(vec![1101, 1, 2, 5, 99, 0]);
+            p.enable_history(10);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(Ok(Status::Halted), p.step());
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(2, p.steps());
+            assert_eq!(Some(2), p.history().map(|h| h.len()));
+
+            // undoing the HLT lets it run again
+            assert!(p.step_back());
+            assert_eq!(Ok(Status::Halted), p.step());
+            assert_eq!(2, p.steps());
+
+            // the same without history, on the fast engine too
+            let mut p = Program::load(vec![1101, 1, 2, 5, 99, 0]);
+            assert_eq!(Ok(Status::Halted), fast::run(&mut p));
+            assert_eq!(Ok(Status::Halted), fast::run(&mut p));
+            assert_eq!(Ok(Status::Halted), p.step());
+            assert_eq!(2, p.steps());
+        }
+
+        #[test]
+        fn waiting_isnt_recorded() {
+            let mut p = Program::load(vec![3, 3, 99, 0]);
+            p.enable_history(10);
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+            assert!(!p.step_back());
+            p.push_input(1);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(Some(2), p.history().map(|h| h.len()));
+
+            // changing memory from outside invalidates the log
+            p.set_memory_at(0, 5);
+            assert!(!p.step_back());
+            p.disable_history();
+            assert!(p.history().is_none());
+        }
+    }
+}
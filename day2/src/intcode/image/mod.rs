@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use super::{varint, Program, Value};
+
+const MAGIC: &[u8; 4] = b"ICIM";
+// Version 1 only checksummed the code, so a damaged header went unnoticed.
+const VERSION: u64 = 2;
+
+const FLAG_COMPRESSED: u64 = 1;
+
+// Bigger than any real program wants, small enough that a corrupt header
+// can't make memory absurdly long.
+pub const MAX_MEMORY_HINT: usize = 1 << 24;
+
+// Back-references shorter than this cost more than the values they replace.
+const MIN_MATCH: usize = 3;
+
+// A program in binary form, with what's needed to run and debug it. On disk
+// it's a header followed by the code as varints, optionally compressed with
+// back-references to earlier runs of values, and then a checksum of all of
+// that.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Image {
+    pub name: String,
+    // Where execution starts.
+    pub entry: usize,
+    // How long memory should be to start with; at least the code's length.
+    pub memory_hint: usize,
+    pub symbols: BTreeMap<String, usize>,
+    pub code: Vec<Value>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// FNV-1a over a stretch of bytes.
+pub fn checksum(data: &[u8]) -> u64 {
+    fnv(FNV_OFFSET, data)
+}
+
+// Passes reads or writes through, hashing every byte on the way.
+struct Hashed<T> {
+    inner: T,
+    hash: u64,
+}
+
+impl<T> Hashed<T> {
+    fn new(inner: T) -> Hashed<T> {
+        Hashed { inner, hash: FNV_OFFSET }
+    }
+}
+
+impl<R: Read> Read for Hashed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hash = fnv(self.hash, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Hashed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash = fnv(self.hash, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_str<W: Write>(out: &mut W, text: &str) -> io::Result<()> {
+    varint::write_u64(out, text.len() as u64)?;
+    out.write_all(text.as_bytes())
+}
+
+fn read_str<R: Read>(input: &mut R) -> io::Result<String> {
+    let len = varint::read_u64(input)?;
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "string cut short"));
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("string isn't UTF-8"))
+}
+
+// Each token is a varint: even for a run of literal values following it,
+// odd for a copy of earlier values followed by how far back they start.
+fn compress<W: Write>(out: &mut W, code: &[Value]) -> io::Result<()> {
+    let mut last_seen: HashMap<&[Value], usize> = HashMap::new();
+    let mut literals = 0;
+    let mut idx = 0;
+    while idx < code.len() {
+        let found = code.get(idx..idx + MIN_MATCH).and_then(|key| last_seen.get(key)).map(|start| {
+            let len = (0..code.len() - idx).take_while(|n| code[start + n] == code[idx + n]).count();
+            (*start, len)
+        });
+        if let Some(key) = code.get(idx..idx + MIN_MATCH) {
+            last_seen.insert(key, idx);
+        }
+        match found {
+            Some((start, len)) => {
+                write_literals(out, &code[idx - literals..idx])?;
+                literals = 0;
+                varint::write_u64(out, ((len - MIN_MATCH) as u64) << 1 | 1)?;
+                varint::write_u64(out, (idx - start) as u64)?;
+                for at in idx + 1..idx + len {
+                    if let Some(key) = code.get(at..at + MIN_MATCH) {
+                        last_seen.insert(key, at);
+                    }
+                }
+                idx += len;
+            }
+            None => {
+                literals += 1;
+                idx += 1;
+            }
+        }
+    }
+    write_literals(out, &code[idx - literals..idx])
+}
+
+fn write_literals<W: Write>(out: &mut W, values: &[Value]) -> io::Result<()> {
+    if values.is_empty() {
+        return Ok(());
+    }
+    varint::write_u64(out, (values.len() as u64) << 1)?;
+    for value in values {
+        varint::write_value(out, *value)?;
+    }
+    Ok(())
+}
+
+fn decompress<R: Read>(input: &mut R, len: usize) -> io::Result<Vec<Value>> {
+    let mut code = Vec::new();
+    while code.len() < len {
+        let token = varint::read_u64(input)?;
+        let count = match token & 1 {
+            0 => token >> 1,
+            _ => (token >> 1).saturating_add(MIN_MATCH as u64),
+        };
+        if count > (len - code.len()) as u64 {
+            return Err(invalid("compressed code runs past its length"));
+        }
+        match token & 1 {
+            0 => {
+                for _ in 0..count {
+                    code.push(varint::read_value(input)?);
+                }
+            }
+            _ => {
+                let distance = varint::read_u64(input)?;
+                if distance == 0 || distance > code.len() as u64 {
+                    return Err(invalid("back-reference out of range"));
+                }
+                // the copy can overlap what it's producing, so go one at a time
+                let start = code.len() - distance as usize;
+                for n in 0..count as usize {
+                    code.push(code[start + n]);
+                }
+            }
+        }
+    }
+    Ok(code)
+}
+
+impl Image {
+    pub fn new(code: Vec<Value>) -> Image {
+        Image { memory_hint: code.len(), code, ..Default::default() }
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W, compressed: bool) -> io::Result<()> {
+        let mut hashed = Hashed::new(&mut *out);
+        self.write_payload(&mut hashed, compressed)?;
+        let sum = hashed.hash;
+        out.write_all(&sum.to_le_bytes())
+    }
+
+    fn write_payload<W: Write>(&self, out: &mut W, compressed: bool) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        varint::write_u64(out, VERSION)?;
+        varint::write_u64(out, if compressed { FLAG_COMPRESSED } else { 0 })?;
+        varint::write_u64(out, self.entry as u64)?;
+        varint::write_u64(out, self.memory_hint as u64)?;
+        write_str(out, &self.name)?;
+        varint::write_u64(out, self.symbols.len() as u64)?;
+        for (name, address) in &self.symbols {
+            write_str(out, name)?;
+            varint::write_u64(out, *address as u64)?;
+        }
+
+        varint::write_u64(out, self.code.len() as u64)?;
+        match compressed {
+            true => compress(out, &self.code),
+            false => self.code.iter().try_for_each(|value| varint::write_value(out, *value)),
+        }
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Image> {
+        let mut hashed = Hashed::new(&mut *input);
+        let image = Image::read_payload(&mut hashed)?;
+        let expected = hashed.hash;
+        let mut sum = [0u8; 8];
+        input.read_exact(&mut sum)?;
+        if u64::from_le_bytes(sum) != expected {
+            return Err(invalid("checksum mismatch"));
+        }
+        Ok(image)
+    }
+
+    fn read_payload<R: Read>(input: &mut R) -> io::Result<Image> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an image"));
+        }
+        match varint::read_u64(input)? {
+            VERSION => (),
+            _ => return Err(invalid("unsupported image version")),
+        }
+        let flags = varint::read_u64(input)?;
+        if flags & !FLAG_COMPRESSED != 0 {
+            return Err(invalid("unknown image flags"));
+        }
+        let entry = varint::read_u64(input)? as usize;
+        let memory_hint = varint::read_u64(input)? as usize;
+        if memory_hint > MAX_MEMORY_HINT {
+            return Err(invalid("memory hint too large"));
+        }
+        let name = read_str(input)?;
+        let mut symbols = BTreeMap::new();
+        for _ in 0..varint::read_u64(input)? {
+            let symbol = read_str(input)?;
+            symbols.insert(symbol, varint::read_u64(input)? as usize);
+        }
+
+        let len = varint::read_u64(input)? as usize;
+        let code = match flags & FLAG_COMPRESSED {
+            0 => {
+                // as with snapshots, don't allocate up front from an
+                // untrusted length
+                let mut code = Vec::new();
+                for _ in 0..len {
+                    code.push(varint::read_value(input)?);
+                }
+                code
+            }
+            _ => decompress(input, len)?,
+        };
+        Ok(Image { name, entry, memory_hint, symbols, code })
+    }
+
+    pub fn save(&self, path: &str, compressed: bool) -> io::Result<()> {
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        self.write_to(&mut out, compressed)?;
+        out.flush()
+    }
+
+    pub fn load(path: &str) -> io::Result<Image> {
+        Image::read_from(&mut BufReader::new(fs::File::open(path)?))
+    }
+
+    // Whether `data` starts like an image, as opposed to program text.
+    pub fn sniff(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    // A program ready to run from the entry point.
+    pub fn program(&self) -> Program {
+        let mut program = Program::load(self.code.clone());
+        program.memory.extend_to(self.memory_hint);
+        program.image.extend_to(self.memory_hint);
+        program.set_entry(self.entry);
+        program
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod image {
+        use super::super::*;
+        use super::super::super::asm::assemble;
+        use super::super::super::Status;
+
+        fn round_trip(image: &Image, compressed: bool) -> Vec<u8> {
+            let mut data = Vec::new();
+            image.write_to(&mut data, compressed).unwrap();
+            assert_eq!(b"ICIM", &data[..4]);
+            assert!(Image::sniff(&data));
+            assert_eq!(*image, Image::read_from(&mut data.as_slice()).unwrap());
+            data
+        }
+
+        #[test]
+        fn write_and_read() {
+            let assembly = assemble("
+            start:  IN [x]
+                    MUL [x], -2, [x]
+                    OUT [x]
+                    HLT
+            x:      .data 0
+            ").unwrap();
+            let mut image = Image::new(assembly.code);
+            image.name = String::from("négatif");
+            image.memory_hint = 100;
+            image.symbols = assembly.labels.iter().map(|(name, addr)| (name.clone(), *addr as usize)).collect();
+            round_trip(&image, false);
+            round_trip(&image, true);
+            round_trip(&Image::default(), true);
+            round_trip(&Image::new(vec![Value::MIN, Value::MAX, -1]), true);
+        }
+
+        #[test]
+        fn compression() {
+            let mut code = vec![0; 1000];
+            for chunk in code.chunks_mut(10).step_by(3) {
+                chunk.copy_from_slice(&[1101, 3, -4, 100, 1005, 100, 7, 99, 12345678, -9]);
+            }
+            let image = Image::new(code);
+            let plain = round_trip(&image, false);
+            let compressed = round_trip(&image, true);
+            assert!(compressed.len() * 10 < plain.len(), "{} vs {}", compressed.len(), plain.len());
+
+            // hand-built: two literals, then five copied from one back
+            let mut data = Vec::new();
+            for token in [4, 2, 4, (2 << 1) | 1, 1] {
+                varint::write_u64(&mut data, token).unwrap();
+            }
+            assert_eq!(vec![1, 2, 2, 2, 2, 2, 2], decompress(&mut data.as_slice(), 7).unwrap());
+            assert!(decompress(&mut data.as_slice(), 4).is_err());
+            assert!(decompress(&mut [3u8, 5].as_ref(), 3).is_err());
+        }
+
+        #[test]
+        fn corruption() {
+            let mut image = Image::new(vec![1, 0, 0, 0, 99]);
+            image.name = String::from("add");
+            image.symbols.insert(String::from("end"), 4);
+            let mut data = Vec::new();
+            image.write_to(&mut data, false).unwrap();
+            assert_eq!(checksum(&data[..data.len() - 8]).to_le_bytes(), data[data.len() - 8..]);
+
+            // the 99, the entry point, a letter of the name, and a symbol's address
+            let end = data.len() - 9;
+            let name = data.iter().position(|b| *b == b'a').unwrap();
+            let symbol = data.iter().position(|b| *b == b'e').unwrap() + 3;
+            for at in [end, 6, name, symbol] {
+                let mut bad = data.clone();
+                bad[at] ^= 2;
+                let error = Image::read_from(&mut bad.as_slice()).unwrap_err();
+                assert_eq!("checksum mismatch", error.to_string(), "byte {}", at);
+            }
+
+            assert!(Image::read_from(&mut &b"ICIX"[..]).is_err());
+            assert!(Image::read_from(&mut &b"ICIM\x01"[..]).is_err());
+            assert!(Image::read_from(&mut &b"ICIM\x03"[..]).is_err());
+            assert!(Image::read_from(&mut &b"ICIM\x02\x04"[..]).is_err());
+            assert!(Image::read_from(&mut &data[..data.len() - 1]).is_err());
+        }
+
+        #[test]
+        fn program() {
+            // the entry point skips an instruction that would fail
+            let mut image = Image::new(vec![42, 104, 7, 99]);
+            image.entry = 1;
+            image.memory_hint = 50;
+            let mut p = image.program();
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![7], p.take_output());
+            assert_eq!(50, p.memory().len());
+            p.reset();
+            assert_eq!(1, p.pc());
+            assert_eq!(50, p.memory().len());
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use super::Value;
+
+// A source of values for the IN instruction. Returning `None` means no value
+// is available yet; if the source also reports itself as exhausted the
+// program can never make progress and IN fails instead of waiting.
+pub trait Input {
+    fn read(&mut self) -> Option<Value>;
+
+    fn exhausted(&self) -> bool {
+        false
+    }
+}
+
+// A destination for values written by the OUT instruction.
+pub trait Output {
+    fn write(&mut self, value: Value);
+}
+
+impl Input for VecDeque<Value> {
+    fn read(&mut self) -> Option<Value> {
+        self.pop_front()
+    }
+}
+
+impl Output for VecDeque<Value> {
+    fn write(&mut self, value: Value) {
+        self.push_back(value)
+    }
+}
+
+impl Output for Vec<Value> {
+    fn write(&mut self, value: Value) {
+        self.push(value)
+    }
+}
+
+// A queue that can be shared between a program and its caller (or between
+// two programs), so values written on one side can be read on the other.
+#[derive(Clone, Default)]
+pub struct Queue {
+    values: Rc<RefCell<VecDeque<Value>>>,
+}
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue::default()
+    }
+
+    pub fn push(&self, value: Value) {
+        self.values.borrow_mut().push_back(value)
+    }
+
+    pub fn pop(&self) -> Option<Value> {
+        self.values.borrow_mut().pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.borrow().is_empty()
+    }
+
+    pub fn drain(&self) -> Vec<Value> {
+        self.values.borrow_mut().drain(..).collect()
+    }
+}
+
+impl Input for Queue {
+    fn read(&mut self) -> Option<Value> {
+        self.pop()
+    }
+}
+
+impl Output for Queue {
+    fn write(&mut self, value: Value) {
+        self.push(value)
+    }
+}
+
+// Feeds values from an iterator; once the iterator ends the input is exhausted.
+pub struct FromIter<I> {
+    iter: I,
+    done: bool,
+}
+
+impl<I: Iterator<Item = Value>> FromIter<I> {
+    pub fn new<T: IntoIterator<IntoIter = I, Item = Value>>(iter: T) -> FromIter<I> {
+        FromIter {
+            iter: iter.into_iter(),
+            done: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Value>> Input for FromIter<I> {
+    fn read(&mut self) -> Option<Value> {
+        match self.iter.next() {
+            Some(value) => Some(value),
+            None => {
+                self.done = true;
+                None
+            }
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.done
+    }
+}
+
+// Asks a closure for each input value.
+pub struct FromFn<F>(pub F);
+
+impl<F: FnMut() -> Option<Value>> Input for FromFn<F> {
+    fn read(&mut self) -> Option<Value> {
+        (self.0)()
+    }
+}
+
+// Hands each output value to a closure.
+pub struct ToFn<F>(pub F);
+
+impl<F: FnMut(Value)> Output for ToFn<F> {
+    fn write(&mut self, value: Value) {
+        (self.0)(value)
+    }
+}
+
+// Reads values from standard input, one or more per line separated by commas
+// or whitespace, prompting on standard error. End of file exhausts the input.
+#[derive(Default)]
+pub struct Stdin {
+    pending: VecDeque<Value>,
+    done: bool,
+}
+
+impl Stdin {
+    pub fn new() -> Stdin {
+        Stdin::default()
+    }
+}
+
+impl Input for Stdin {
+    fn read(&mut self) -> Option<Value> {
+        let stdin = io::stdin();
+        while self.pending.is_empty() && !self.done {
+            // the prompt goes to stderr so it doesn't end up mixed into the
+            // program's output
+            eprint!("input> ");
+            let _ = io::stderr().flush();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => self.done = true,
+                Ok(_) => {
+                    for item in line.split(|c: char| c == ',' || c.is_whitespace()) {
+                        match item {
+                            "" => continue,
+                            item => match item.parse::<Value>() {
+                                Ok(value) => self.pending.push_back(value),
+                                Err(_) => eprintln!("invalid input value: {}", item),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.pending.pop_front()
+    }
+
+    fn exhausted(&self) -> bool {
+        self.done && self.pending.is_empty()
+    }
+}
+
+// Prints each value to standard output on its own line.
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn write(&mut self, value: Value) {
+        println!("{}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod io {
+        use super::super::*;
+
+        #[test]
+        fn vec_deque_input() {
+            let mut input: VecDeque<Value> = vec![1, 2].into_iter().collect();
+            assert_eq!(Some(1), input.read());
+            assert_eq!(Some(2), input.read());
+            assert_eq!(None, input.read());
+            assert!(!input.exhausted());
+        }
+
+        #[test]
+        fn queue_is_shared() {
+            let queue = Queue::new();
+            let mut writer = queue.clone();
+            writer.write(5);
+            writer.write(6);
+            assert_eq!(2, queue.len());
+            assert_eq!(vec![5, 6], queue.drain());
+            assert!(queue.is_empty());
+        }
+
+        #[test]
+        fn from_iter_exhausts() {
+            let mut input = FromIter::new(vec![7]);
+            assert!(!input.exhausted());
+            assert_eq!(Some(7), input.read());
+            assert_eq!(None, input.read());
+            assert!(input.exhausted());
+        }
+
+        #[test]
+        fn closures() {
+            let mut next = 0;
+            let mut input = FromFn(move || {
+                next += 1;
+                Some(next)
+            });
+            assert_eq!(Some(1), input.read());
+            assert_eq!(Some(2), input.read());
+
+            let mut seen = Vec::new();
+            {
+                let mut output = ToFn(|value| seen.push(value));
+                output.write(3);
+                output.write(4);
+            }
+            assert_eq!(vec![3, 4], seen);
+        }
+    }
+}
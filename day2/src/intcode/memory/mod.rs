@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::Value;
+
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+
+// Pages below this index live in a vector, anything above it is kept in a
+// map so a single write to a huge address doesn't allocate everything below.
+const DENSE_PAGES: usize = 1 << 12;
+
+type Page = [Value; PAGE_SIZE];
+
+// Paged memory that grows on demand. Cells that were never written read as 0.
+// Pages are shared between clones until one of them writes, so cloning is
+// cheap enough to fork a running machine.
+#[derive(Clone, Default)]
+pub struct Memory {
+    dense: Vec<Option<Arc<Page>>>,
+    sparse: HashMap<usize, Arc<Page>>,
+    len: usize,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    pub fn from_slice(values: &[Value]) -> Memory {
+        let mut memory = Memory::new();
+        for (addr, value) in values.iter().enumerate() {
+            memory.set(addr, *value);
+        }
+        memory.len = values.len();
+        memory
+    }
+
+    pub fn get(&self, addr: usize) -> Value {
+        let page = addr >> PAGE_BITS;
+        let found = match page {
+            p if p < self.dense.len() => self.dense[p].as_ref(),
+            p if p >= DENSE_PAGES => self.sparse.get(&p),
+            _ => None,
+        };
+        match found {
+            Some(values) => values[addr & PAGE_MASK],
+            None => 0,
+        }
+    }
+
+    pub fn set(&mut self, addr: usize, value: Value) {
+        let page = addr >> PAGE_BITS;
+        let values = match page {
+            p if p < DENSE_PAGES => {
+                if p >= self.dense.len() {
+                    self.dense.resize_with(p + 1, || None);
+                }
+                self.dense[p].get_or_insert_with(|| Arc::new([0; PAGE_SIZE]))
+            }
+            p => self.sparse.entry(p).or_insert_with(|| Arc::new([0; PAGE_SIZE])),
+        };
+        Arc::make_mut(values)[addr & PAGE_MASK] = value;
+
+        // the length can't go past the last address, so it sticks there
+        if addr >= self.len {
+            self.len = addr.saturating_add(1);
+        }
+    }
+
+    // One past the highest address that was loaded or written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // How far the dense pages reach, which can be well short of `len` after
+    // a write to a huge address.
+    pub fn dense_len(&self) -> usize {
+        (self.dense.len() << PAGE_BITS).min(self.len)
+    }
+
+    // The number of pages currently allocated.
+    pub fn pages(&self) -> usize {
+        self.dense.iter().filter(|p| p.is_some()).count() + self.sparse.len()
+    }
+
+    // Grow the length to at least `len` without writing anything.
+    pub fn extend_to(&mut self, len: usize) {
+        self.len = self.len.max(len)
+    }
+
+    pub fn to_vec(&self) -> Vec<Value> {
+        (0..self.len).map(|addr| self.get(addr)).collect()
+    }
+
+    // Every cell holding something other than 0, in address order.
+    pub fn nonzero(&self) -> Vec<(usize, Value)> {
+        let dense = self.dense
+            .iter()
+            .enumerate()
+            .filter_map(|(page, values)| values.as_ref().map(|values| (page, values)));
+        let mut sparse: Vec<(usize, &Arc<Page>)> = self.sparse.iter().map(|(page, values)| (*page, values)).collect();
+        sparse.sort_by_key(|(page, _)| *page);
+
+        dense
+            .chain(sparse)
+            .flat_map(|(page, values)| {
+                values
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, value)| **value != 0)
+                    .map(move |(offset, value)| ((page << PAGE_BITS) + offset, *value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod memory {
+        use super::super::*;
+
+        #[test]
+        fn from_slice() {
+            let m = Memory::from_slice(&[1, 2, 3]);
+            assert_eq!(3, m.len());
+            assert_eq!(vec![1, 2, 3], m.to_vec());
+            assert_eq!(1, m.pages());
+        }
+
+        #[test]
+        fn unwritten_reads_zero() {
+            let m = Memory::new();
+            assert!(m.is_empty());
+            assert_eq!(0, m.get(0));
+            assert_eq!(0, m.get(usize::MAX));
+            assert_eq!(0, m.pages());
+        }
+
+        #[test]
+        fn grows_on_write() {
+            let mut m = Memory::from_slice(&[1]);
+            m.set(3, -4);
+            assert_eq!(vec![1, 0, 0, -4], m.to_vec());
+        }
+
+        #[test]
+        fn huge_address_is_sparse() {
+            let mut m = Memory::new();
+            m.set(1 << 40, 7);
+            assert_eq!(7, m.get(1 << 40));
+            assert_eq!(0, m.get((1 << 40) + 1));
+            assert_eq!(1, m.pages());
+            assert_eq!((1 << 40) + 1, m.len());
+            assert_eq!(0, m.dense_len());
+            m.set(3, 1);
+            assert_eq!(PAGE_SIZE, m.dense_len());
+        }
+
+        #[test]
+        fn last_address() {
+            let mut m = Memory::new();
+            m.set(usize::MAX, 3);
+            assert_eq!(3, m.get(usize::MAX));
+            assert_eq!(usize::MAX, m.len());
+            m.set(5, 1);
+            assert_eq!(usize::MAX, m.len());
+        }
+
+        #[test]
+        fn clones_share_until_written() {
+            let mut a = Memory::from_slice(&[1, 2, 3]);
+            let b = a.clone();
+            a.set(0, 9);
+            assert_eq!(vec![9, 2, 3], a.to_vec());
+            assert_eq!(vec![1, 2, 3], b.to_vec());
+        }
+
+        #[test]
+        fn nonzero() {
+            let mut m = Memory::from_slice(&[0, 5, 0]);
+            m.set(1 << 40, 7);
+            m.set(PAGE_SIZE + 1, -1);
+            assert_eq!(vec![(1, 5), (PAGE_SIZE + 1, -1), (1 << 40, 7)], m.nonzero());
+        }
+
+        #[test]
+        fn extend_to() {
+            let mut m = Memory::from_slice(&[1]);
+            m.extend_to(3);
+            m.extend_to(2);
+            assert_eq!(vec![1, 0, 0], m.to_vec());
+        }
+    }
+}
\ No newline at end of file
@@ -1,157 +1,764 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::fs;
-use std::error::Error;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::str::FromStr;
 
-type Opcode = u32;
+pub mod analysis;
+pub mod ascii;
+pub mod asm;
+pub mod budget;
+pub mod compile;
+pub mod debug;
+pub mod device;
+pub mod disasm;
+pub mod error;
+pub mod fast;
+pub mod fuzz;
+pub mod history;
+pub mod image;
+pub mod io;
+pub mod memory;
+pub mod network;
+pub mod parse;
+pub mod replay;
+pub mod sched;
+pub mod search;
+pub mod snapshot;
+pub mod symbolic;
+#[cfg(test)]
+mod testing;
+pub mod trace;
+pub mod varint;
+
+pub use self::error::Error;
+use memory::Memory;
+
+pub type Value = i64;
+
+const OP_ADD: Value = 1;
+const OP_MUL: Value = 2;
+const OP_IN: Value = 3;
+const OP_OUT: Value = 4;
+const OP_JT: Value = 5;
+const OP_JF: Value = 6;
+const OP_LT: Value = 7;
+const OP_EQ: Value = 8;
+const OP_ARB: Value = 9;
+const OP_HLT: Value = 99;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OpInfo {
+    pub op: Value,
+    pub mnemonic: &'static str,
+    pub params: usize,
+    // Whether the last parameter is a destination that gets written to.
+    pub writes: bool,
+}
+
+const OPS: [OpInfo; 10] = [
+    OpInfo { op: OP_ADD, mnemonic: "ADD", params: 3, writes: true },
+    OpInfo { op: OP_MUL, mnemonic: "MUL", params: 3, writes: true },
+    OpInfo { op: OP_IN, mnemonic: "IN", params: 1, writes: true },
+    OpInfo { op: OP_OUT, mnemonic: "OUT", params: 1, writes: false },
+    OpInfo { op: OP_JT, mnemonic: "JT", params: 2, writes: false },
+    OpInfo { op: OP_JF, mnemonic: "JF", params: 2, writes: false },
+    OpInfo { op: OP_LT, mnemonic: "LT", params: 3, writes: true },
+    OpInfo { op: OP_EQ, mnemonic: "EQ", params: 3, writes: true },
+    OpInfo { op: OP_ARB, mnemonic: "ARB", params: 1, writes: false },
+    OpInfo { op: OP_HLT, mnemonic: "HLT", params: 0, writes: false },
+];
+
+pub fn op_info(op: Value) -> Option<OpInfo> {
+    OPS.iter().find(|info| info.op == op).cloned()
+}
+
+pub fn op_by_mnemonic(mnemonic: &str) -> Option<OpInfo> {
+    OPS.iter().find(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic)).cloned()
+}
+
+const MODE_POSITION: Value = 0;
+const MODE_IMMEDIATE: Value = 1;
+const MODE_RELATIVE: Value = 2;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    fn from_digit(digit: Value) -> Option<Mode> {
+        match digit {
+            MODE_POSITION => Some(Mode::Position),
+            MODE_IMMEDIATE => Some(Mode::Immediate),
+            MODE_RELATIVE => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Instruction {
+    pub raw: Value,
+    pub op: Value,
+    pub modes: [Mode; 3],
+}
+
+impl Instruction {
+    // Split the raw value found at `pc` into its opcode and parameter modes.
+    pub fn decode(pc: usize, raw: Value) -> Result<Instruction, Error> {
+        if raw < 0 {
+            return Err(Error::UnknownOpcode { pc, instruction: raw });
+        }
+
+        let mut modes = [Mode::Position; 3];
+        let mut digits = raw / 100;
+        for (idx, mode) in modes.iter_mut().enumerate() {
+            *mode = match Mode::from_digit(digits % 10) {
+                Some(mode) => mode,
+                None => return Err(Error::BadMode { pc, instruction: raw, param: idx + 1 }),
+            };
+            digits /= 10;
+        }
+        if digits != 0 {
+            return Err(Error::BadMode { pc, instruction: raw, param: modes.len() + 1 });
+        }
+
+        Ok(Instruction {
+            raw,
+            op: raw % 100,
+            modes,
+        })
+    }
+
+    // The mode for the 1-based parameter `param`.
+    pub fn mode(&self, param: usize) -> Mode {
+        self.modes[param - 1]
+    }
+}
 
-const OP_ADD: Opcode = 1;
-const OP_MUL: Opcode = 2;
-const OP_HLT: Opcode = 99;
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Status {
+    Running,
+    Halted,
+    WaitingForInput,
+}
 
 pub struct Program {
     pc: usize,
-    memory: Vec<Opcode>,
-    code: Vec<Opcode>,
+    // Where the program starts, and goes back to on reset.
+    entry: usize,
+    relative_base: Value,
+    memory: Memory,
+    image: Memory,
+    inputs: VecDeque<Value>,
+    outputs: VecDeque<Value>,
+    input: Option<Box<dyn io::Input>>,
+    output: Option<Box<dyn io::Output>>,
+    last_write: Option<(usize, Value)>,
+    // Set once HLT has run, so stepping again doesn't run it again.
+    halted: bool,
+    steps: u64,
+    tracer: Option<Box<dyn trace::Tracer>>,
+    // Values read by the current instruction, only collected while tracing.
+    reads: Vec<Value>,
+    budget: budget::Budget,
+    detector: budget::LoopDetector,
+    devices: Vec<(Range<usize>, Box<dyn device::Device>)>,
+    history: Option<history::History>,
 }
 
 impl Program {
-    pub fn load(code: Vec<Opcode>) -> Program {
+    pub fn load(code: Vec<Value>) -> Program {
         Program {
             pc: 0,
-            memory: code.clone(),
-            code,
-        }
-    }
-
-    pub fn load_from_file(path: &str) -> Result<Program, String> {
-        match fs::read_to_string(path) {
-            Ok(data) => {
-                let mut code: Vec<Opcode> = Vec::new();
-                for item in data.split(',') {
-                    match item.trim() {
-                        "" => continue,
-                        opcode => match opcode.parse::<Opcode>() {
-                            Ok(opcode) => code.push(opcode),
-                            Err(_) => return Err(format!("invalid opcode found: {}", opcode))
-                        }
-                    }
-                }
+            entry: 0,
+            relative_base: 0,
+            memory: Memory::from_slice(&code),
+            image: Memory::from_slice(&code),
+            inputs: VecDeque::new(),
+            outputs: VecDeque::new(),
+            input: None,
+            output: None,
+            last_write: None,
+            halted: false,
+            steps: 0,
+            tracer: None,
+            reads: Vec::new(),
+            budget: budget::Budget::default(),
+            detector: budget::LoopDetector::new(),
+            devices: Vec::new(),
+            history: None,
+        }
+    }
 
-                Ok(Program::load(code))
-            }
-            Err(e) => Err(e.description().to_string()),
+    // Load either program text or a binary image.
+    pub fn load_from_file(path: &str) -> Result<Program, Error> {
+        let io_error = |e: std::io::Error| Error::Io(e.to_string());
+        let mut input = BufReader::new(fs::File::open(path).map_err(io_error)?);
+        if image::Image::sniff(input.fill_buf().map_err(io_error)?) {
+            return image::Image::read_from(&mut input).map(|image| image.program()).map_err(io_error);
         }
+        parse::parse(input).map(Program::load)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
     }
 
-    pub fn memory_at(&self, idx: usize) -> Option<Opcode> {
+    pub fn memory_at(&self, idx: usize) -> Option<Value> {
         match self.memory.len() {
-            size if idx < size => Some(self.memory[idx]),
+            size if idx < size => Some(self.memory.get(idx)),
             _ => None,
         }
     }
 
+    // Change a cell from outside the program. This clears the history, which
+    // couldn't undo the change.
+    pub fn set_memory_at(&mut self, idx: usize, value: Value) {
+        self.detector.reset();
+        self.clear_history();
+        // overwriting the HLT lets the program carry on
+        self.halted &= idx != self.pc;
+        self.memory.set(idx, value)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // Start running at `pc`, now and after every reset.
+    pub fn set_entry(&mut self, pc: usize) {
+        self.entry = pc;
+        self.pc = pc;
+        self.halted = false;
+    }
+
+    pub fn relative_base(&self) -> Value {
+        self.relative_base
+    }
+
+    // The address written by the most recently executed instruction, if it
+    // wrote anything.
+    pub fn last_write(&self) -> Option<usize> {
+        self.last_write.map(|(addr, _)| addr)
+    }
+
+    // Queue a value to be read by IN before the input source is consulted.
+    pub fn push_input(&mut self, value: Value) {
+        self.inputs.push_back(value)
+    }
+
+    // Put a value back at the end of the output buffer, e.g. when only part of
+    // what was taken could be used.
+    pub fn push_output(&mut self, value: Value) {
+        self.outputs.push_back(value)
+    }
+
+    // Take every value written by OUT since the last call. Values only end up
+    // here when no output sink has been set.
+    pub fn take_output(&mut self) -> Vec<Value> {
+        self.outputs.drain(..).collect()
+    }
+
+    pub fn set_input<I: io::Input + 'static>(&mut self, input: I) {
+        self.input = Some(Box::new(input))
+    }
+
+    pub fn set_output<O: io::Output + 'static>(&mut self, output: O) {
+        self.output = Some(Box::new(output))
+    }
+
+    // Call `tracer` for every instruction executed from now on.
+    pub fn set_tracer<T: trace::Tracer + 'static>(&mut self, tracer: T) {
+        self.tracer = Some(Box::new(tracer))
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.tracer = None
+    }
+
+    // Map `device` over the addresses in `range`.
+    pub fn attach<D: device::Device + 'static>(&mut self, range: Range<usize>, device: D) -> Result<(), device::Overlap> {
+        if let Some((existing, _)) = self.devices.iter().find(|(r, _)| r.start < range.end && range.start < r.end) {
+            return Err(device::Overlap { existing: existing.clone() });
+        }
+        self.devices.push((range, Box::new(device)));
+        Ok(())
+    }
+
+    // Remove the device covering `address`, returning whether there was one.
+    pub fn detach(&mut self, address: usize) -> bool {
+        let before = self.devices.len();
+        self.devices.retain(|(range, _)| !range.contains(&address));
+        self.devices.len() != before
+    }
+
+    // Keep an undo log of the last `limit` instructions so they can be
+    // stepped back over.
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history = Some(history::History::new(limit))
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None
+    }
+
+    pub fn history(&self) -> Option<&history::History> {
+        self.history.as_ref()
+    }
+
+    fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    // Undo the most recently executed instruction, returning false once the
+    // history runs out. Output already taken or sent to a sink can't be taken
+    // back, and neither can anything a device did.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history.as_mut().and_then(|history| history.pop()) {
+            Some(undo) => undo,
+            None => return false,
+        };
+        self.pc = undo.pc;
+        self.relative_base = undo.relative_base;
+        self.steps = undo.step;
+        if let Some((address, old)) = undo.write {
+            self.memory.set(address, old);
+        }
+        if let Some(value) = undo.input {
+            self.inputs.push_front(value);
+        }
+        if let (Some(value), None) = (undo.output, &self.output) {
+            if self.outputs.back() == Some(&value) {
+                self.outputs.pop_back();
+            }
+        }
+        self.last_write = None;
+        self.halted = false;
+        self.detector.reset();
+        true
+    }
+
+    pub fn budget(&self) -> budget::Budget {
+        self.budget
+    }
+
+    // Limit how far the program may run from now on. Steps count from when
+    // the program was loaded or last reset.
+    pub fn set_budget(&mut self, budget: budget::Budget) {
+        self.budget = budget;
+        self.detector.reset();
+    }
+
+    // Whether something has to see every instruction as `step` runs it, so
+    // the faster engines can't be used.
+    fn needs_interpreter(&self) -> bool {
+        self.tracer.is_some()
+            || self.budget != budget::Budget::default()
+            || !self.devices.is_empty()
+            || self.history.is_some()
+    }
+
+    // The number of instructions executed since loading or resetting.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Restore the original code image. Buffered input and output values are
+    // discarded, attached sources and sinks are kept.
     pub fn reset(&mut self) {
-        self.memory = self.code.clone();
-        self.pc = 0;
+        self.memory = self.image.clone();
+        self.pc = self.entry;
+        self.relative_base = 0;
+        self.last_write = None;
+        self.halted = false;
+        self.steps = 0;
+        self.inputs.clear();
+        self.outputs.clear();
+        self.detector.reset();
+        self.clear_history();
+    }
+
+    pub fn snapshot(&self) -> snapshot::Snapshot {
+        snapshot::Snapshot {
+            memory: self.memory.clone(),
+            pc: self.pc,
+            relative_base: self.relative_base,
+            steps: self.steps,
+            halted: self.halted,
+            inputs: self.inputs.iter().cloned().collect(),
+            outputs: self.outputs.iter().cloned().collect(),
+        }
+    }
+
+    // Put the machine back into the state captured by `snapshot`. Attached
+    // sources, sinks and tracers are left alone.
+    pub fn restore(&mut self, snapshot: &snapshot::Snapshot) {
+        self.memory = snapshot.memory.clone();
+        self.pc = snapshot.pc;
+        self.relative_base = snapshot.relative_base;
+        self.steps = snapshot.steps;
+        self.last_write = None;
+        self.halted = snapshot.halted;
+        self.inputs = snapshot.inputs.iter().cloned().collect();
+        self.outputs = snapshot.outputs.iter().cloned().collect();
+        self.detector.reset();
+        self.clear_history();
     }
 
-    pub fn run(&mut self) -> Result<bool, String> {
+    // A copy of the machine that shares memory with this one until either
+    // writes to it. Sources, sinks, tracers and devices can't be shared, so
+    // the fork starts without any and buffers its I/O.
+    pub fn fork(&self) -> Program {
+        let mut program = Program {
+            pc: 0,
+            entry: self.entry,
+            relative_base: 0,
+            memory: Memory::new(),
+            image: self.image.clone(),
+            inputs: VecDeque::new(),
+            outputs: VecDeque::new(),
+            input: None,
+            output: None,
+            last_write: None,
+            halted: false,
+            steps: 0,
+            tracer: None,
+            reads: Vec::new(),
+            budget: self.budget,
+            detector: budget::LoopDetector::new(),
+            devices: Vec::new(),
+            history: None,
+        };
+        program.restore(&self.snapshot());
+        program
+    }
+
+    // Run until the program halts or needs input that isn't available yet.
+    // A program waiting for input can be resumed by calling run again.
+    pub fn run(&mut self) -> Result<Status, Error> {
         loop {
             match self.step() {
-                Ok(complete) => match complete {
-                    true => return Ok(true),
-                    _ => continue,
-                }
-                e => return e,
+                Ok(Status::Running) => continue,
+                result => return result,
             }
         }
     }
 
-    pub fn call(&mut self, noun: Opcode, verb: Opcode) -> Result<Opcode, String> {
+    pub fn call(&mut self, noun: Value, verb: Value) -> Result<Value, Error> {
         self.reset();
-        match self.memory.len() {
-            l if l < 3 => Err(format!("invalid program length")),
-            _ => {
-                self.memory[1] = noun;
-                self.memory[2] = verb;
-                match self.run() {
-                    Ok(_) => match self.memory_at(0) {
-                        Some(opcode) => Ok(opcode),
-                        None => Err(format!("result value not found"))
-                    }
-                    Err(e) => Err(format!("error running program: {}", e))
-                }
+        self.memory.set(1, noun);
+        self.memory.set(2, verb);
+        self.run()?;
+        Ok(self.memory.get(0))
+    }
+
+    // Execute a single instruction. Once halted, stepping does nothing.
+    pub fn step(&mut self) -> Result<Status, Error> {
+        if self.halted {
+            return Ok(Status::Halted);
+        }
+        if let Some(limit) = self.budget.steps {
+            if self.steps >= limit {
+                return Err(Error::StepLimit { pc: self.pc, limit });
             }
         }
+        self.last_write = None;
+        self.reads.clear();
+        let pc = self.pc;
+        if let Some(history) = &mut self.history {
+            history.begin(self.steps, pc, self.relative_base);
+        }
+        let inst = Instruction::decode(self.pc, self.memory.get(self.pc))?;
+        // the operand words are traced as they were executed, before the
+        // instruction has a chance to overwrite them
+        let operands: Vec<Value> = match &self.tracer {
+            Some(_) => {
+                let params = op_info(inst.op).map(|info| info.params).unwrap_or(0);
+                (1..=params).map(|p| self.memory.get(pc + p)).collect()
+            }
+            None => Vec::new(),
+        };
+        let status = match inst.op {
+            OP_ADD => self.op_add(inst),
+            OP_MUL => self.op_mul(inst),
+            OP_IN => self.op_in(inst),
+            OP_OUT => self.op_out(inst),
+            OP_JT => self.op_jump(inst, OP_JT),
+            OP_JF => self.op_jump(inst, OP_JF),
+            OP_LT => self.op_compare(inst, OP_LT),
+            OP_EQ => self.op_compare(inst, OP_EQ),
+            OP_ARB => self.op_arb(inst),
+            OP_HLT => Ok(Status::Halted),
+            _ => Err(self.unknown_opcode(&inst)),
+        }?;
+        self.halted = status == Status::Halted;
+
+        if status != Status::WaitingForInput {
+            if let Some(tracer) = &mut self.tracer {
+                let event = trace::Event {
+                    step: self.steps,
+                    pc,
+                    instruction: inst,
+                    operands,
+                    reads: self.reads.clone(),
+                    write: self.last_write,
+                };
+                tracer.trace(&event);
+            }
+            self.steps += 1;
+            for (_, device) in &mut self.devices {
+                device.tick();
+            }
+            if let Some(history) = &mut self.history {
+                history.commit();
+            }
+        }
+
+        if self.budget.detect_loops && status == Status::Running {
+            if let Some(period) = self.detector.observe(self.pc, self.relative_base, &self.memory) {
+                return Err(Error::Loop { pc: self.pc, period });
+            }
+        }
+
+        Ok(status)
+    }
+
+    fn unknown_opcode(&self, inst: &Instruction) -> Error {
+        Error::UnknownOpcode { pc: self.pc, instruction: inst.raw }
+    }
+
+    fn overflow(&self, inst: &Instruction, a: Value, b: Value) -> Error {
+        Error::Overflow { pc: self.pc, instruction: inst.raw, a, b }
     }
 
-    fn step(&mut self) -> Result<bool, String> {
-        let code = self.memory[self.pc];
-        match code {
-            OP_ADD => self.op_add(),
-            OP_MUL => self.op_mul(),
-            OP_HLT => Ok(true),
-            _ => Err(format!("unknown op code {}", code))
+    // Resolve the (possibly negative) address referenced by the 1-based
+    // parameter `param` of the current instruction. Immediate parameters have
+    // no address.
+    fn param_addr(&self, inst: &Instruction, param: usize) -> Result<Value, Error> {
+        let operand = self.memory.get(self.pc + param);
+        match inst.mode(param) {
+            Mode::Position => Ok(operand),
+            Mode::Relative => match self.relative_base.checked_add(operand) {
+                Some(addr) => Ok(addr),
+                None => Err(self.overflow(inst, self.relative_base, operand)),
+            }
+            Mode::Immediate => Err(Error::BadMode { pc: self.pc, instruction: inst.raw, param }),
         }
     }
 
-    fn op_add(&mut self) -> Result<bool, String> {
-        if self.memory[self.pc] != OP_ADD {
-            return Err(String::from("OP_ADD: unexpected opcode"));
+    fn write_addr(&self, inst: &Instruction, param: usize) -> Result<usize, Error> {
+        match (self.param_addr(inst, param)?, self.budget.memory) {
+            (a, _) if a < 0 => Err(Error::WriteOutOfBounds { pc: self.pc, instruction: inst.raw, address: a }),
+            (a, Some(limit)) if a as u64 >= limit as u64 =>
+                Err(Error::MemoryLimit { pc: self.pc, instruction: inst.raw, address: a as usize }),
+            (a, _) => Ok(a as usize),
         }
+    }
 
-        let mem_len = self.memory.len();
+    fn read_param(&mut self, inst: &Instruction, param: usize) -> Result<Value, Error> {
+        let value = match inst.mode(param) {
+            Mode::Immediate => self.memory.get(self.pc + param),
+            _ => match self.param_addr(inst, param)? {
+                a if a < 0 => return Err(Error::ReadOutOfBounds { pc: self.pc, instruction: inst.raw, address: a }),
+                a => self.read_addr(a as usize),
+            }
+        };
+        if self.tracer.is_some() {
+            self.reads.push(value);
+        }
+        Ok(value)
+    }
 
-        if self.pc + 3 > mem_len {
-            return Err(String::from("OP_ADD: invalid length"));
+    fn write_param(&mut self, inst: &Instruction, param: usize, value: Value) -> Result<(), Error> {
+        let addr = self.write_addr(inst, param)?;
+        self.last_write = Some((addr, value));
+        if let Some((range, device)) = self.devices.iter_mut().find(|(r, _)| r.contains(&addr)) {
+            device.write(addr - range.start, value);
+            return Ok(());
+        }
+        if self.budget.detect_loops {
+            self.detector.write(addr, self.memory.get(addr), value);
+        }
+        if let Some(history) = &mut self.history {
+            history.wrote(addr, self.memory.get(addr));
         }
+        self.memory.set(addr, value);
+        Ok(())
+    }
 
-        let a = self.memory[self.pc + 1] as usize;
-        if a > mem_len {
-            return Err(format!("OP_ADD: a value out of range: {}", a));
+    // Read memory, or the device mapped over it.
+    fn read_addr(&mut self, addr: usize) -> Value {
+        match self.devices.iter_mut().find(|(r, _)| r.contains(&addr)) {
+            Some((range, device)) => {
+                // what a device returns isn't part of the state the loop
+                // detector sees
+                self.detector.reset();
+                device.read(addr - range.start)
+            }
+            None => self.memory.get(addr),
         }
-        let b = self.memory[self.pc + 2] as usize;
-        if b > mem_len {
-            return Err(format!("OP_ADD: b value out of range: {}", b));
+    }
+
+    fn op_add(&mut self, inst: Instruction) -> Result<Status, Error> {
+        if inst.op != OP_ADD {
+            return Err(self.unknown_opcode(&inst));
         }
-        let dest = self.memory[self.pc + 3] as usize;
-        if dest > mem_len {
-            return Err(format!("OP_ADD: dest value out of range: {}", dest));
+
+        let a = self.read_param(&inst, 1)?;
+        let b = self.read_param(&inst, 2)?;
+        let sum = match a.checked_add(b) {
+            Some(sum) => sum,
+            None => return Err(self.overflow(&inst, a, b)),
+        };
+        self.write_param(&inst, 3, sum)?;
+        self.pc += 4;
+
+        Ok(Status::Running)
+    }
+
+    fn op_mul(&mut self, inst: Instruction) -> Result<Status, Error> {
+        if inst.op != OP_MUL {
+            return Err(self.unknown_opcode(&inst));
         }
 
-        self.memory[dest] = self.memory[a] + self.memory[b];
+        let a = self.read_param(&inst, 1)?;
+        let b = self.read_param(&inst, 2)?;
+        let product = match a.checked_mul(b) {
+            Some(product) => product,
+            None => return Err(self.overflow(&inst, a, b)),
+        };
+        self.write_param(&inst, 3, product)?;
         self.pc += 4;
 
-        Ok(false)
+        Ok(Status::Running)
     }
 
-    fn op_mul(&mut self) -> Result<bool, String> {
-        if self.memory[self.pc] != OP_MUL {
-            return Err(String::from("OP_MUL: unexpected opcode"));
+    fn op_in(&mut self, inst: Instruction) -> Result<Status, Error> {
+        if inst.op != OP_IN {
+            return Err(self.unknown_opcode(&inst));
         }
 
-        let mem_len = self.memory.len();
+        // Check the destination before consuming a value so a bad write
+        // doesn't lose input.
+        self.write_addr(&inst, 1)?;
 
-        if self.pc + 3 > mem_len {
-            return Err(String::from("OP_MUL: invalid length"));
+        let value = match self.inputs.pop_front() {
+            Some(value) => value,
+            None => match &mut self.input {
+                Some(input) => match input.read() {
+                    Some(value) => value,
+                    None if input.exhausted() => return Err(Error::InputExhausted { pc: self.pc, instruction: inst.raw }),
+                    None => return Ok(Status::WaitingForInput),
+                }
+                None => return Ok(Status::WaitingForInput),
+            }
+        };
+        // the program can't be stuck if it's still taking input
+        self.detector.reset();
+        if let Some(history) = &mut self.history {
+            history.consumed(value);
         }
+        self.write_param(&inst, 1, value)?;
+        self.pc += 2;
 
-        let a = self.memory[self.pc + 1] as usize;
-        if a > mem_len {
-            return Err(format!("OP_MUL: a value out of range: {}", a));
+        Ok(Status::Running)
+    }
+
+    fn op_out(&mut self, inst: Instruction) -> Result<Status, Error> {
+        if inst.op != OP_OUT {
+            return Err(self.unknown_opcode(&inst));
+        }
+
+        let value = self.read_param(&inst, 1)?;
+        if let Some(history) = &mut self.history {
+            history.produced(value);
         }
-        let b = self.memory[self.pc + 2] as usize;
-        if b > mem_len {
-            return Err(format!("OP_MUL: b value out of range: {}", b));
+        match &mut self.output {
+            Some(output) => output.write(value),
+            None => self.outputs.push_back(value),
+        }
+        self.pc += 2;
+
+        Ok(Status::Running)
+    }
+
+    // Shared by jump-if-true and jump-if-false, which only differ in the
+    // condition checked against the first parameter.
+    fn op_jump(&mut self, inst: Instruction, op: Value) -> Result<Status, Error> {
+        if inst.op != op {
+            return Err(self.unknown_opcode(&inst));
         }
-        let dest = self.memory[self.pc + 3] as usize;
-        if dest > mem_len {
-            return Err(format!("OP_MUL: dest value out of range: {}", dest));
+
+        let cond = self.read_param(&inst, 1)?;
+        let target = self.read_param(&inst, 2)?;
+        let jump = match op {
+            OP_JT => cond != 0,
+            _ => cond == 0,
+        };
+        match target {
+            _ if !jump => self.pc += 3,
+            t if t < 0 => return Err(Error::JumpOutOfBounds { pc: self.pc, instruction: inst.raw, target: t }),
+            t => self.pc = t as usize,
         }
 
-        self.memory[dest] = self.memory[a] * self.memory[b];
+        Ok(Status::Running)
+    }
+
+    // Shared by less-than and equals, which store 1 or 0 depending on how the
+    // first two parameters compare.
+    fn op_compare(&mut self, inst: Instruction, op: Value) -> Result<Status, Error> {
+        if inst.op != op {
+            return Err(self.unknown_opcode(&inst));
+        }
+
+        let a = self.read_param(&inst, 1)?;
+        let b = self.read_param(&inst, 2)?;
+        let result = match op {
+            OP_LT => a < b,
+            _ => a == b,
+        };
+        self.write_param(&inst, 3, result as Value)?;
         self.pc += 4;
 
-        Ok(false)
+        Ok(Status::Running)
+    }
+
+    fn op_arb(&mut self, inst: Instruction) -> Result<Status, Error> {
+        if inst.op != OP_ARB {
+            return Err(self.unknown_opcode(&inst));
+        }
+
+        let offset = self.read_param(&inst, 1)?;
+        self.relative_base = match self.relative_base.checked_add(offset) {
+            Some(base) => base,
+            None => return Err(self.overflow(&inst, self.relative_base, offset)),
+        };
+        self.pc += 2;
+
+        Ok(Status::Running)
+    }
+}
+
+impl FromStr for Program {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Program, Error> {
+        parse::parse(text.as_bytes()).map(Program::load)
+    }
+}
+
+impl TryFrom<&str> for Program {
+    type Error = Error;
+
+    fn try_from(text: &str) -> Result<Program, Error> {
+        text.parse()
     }
 }
 
@@ -169,75 +776,103 @@ mod tests {
             assert_eq!(Some(3), p.memory_at(3));
         }
 
+        #[test]
+        fn from_str() {
+            let p: Program = "1,0,0,0, # add\n99,".parse().unwrap();
+            assert_eq!(vec![1, 0, 0, 0, 99], p.memory.to_vec());
+            let p = Program::try_from("104,-1,99").unwrap();
+            assert_eq!(vec![104, -1, 99], p.memory.to_vec());
+            assert_eq!(
+                Err(Error::Parse { line: 2, column: 1, token: String::from("x") }),
+                Program::try_from("1,\nx").map(|p| p.pc),
+            );
+        }
+
+        #[test]
+        fn load_from_file() {
+            let p = Program::load_from_file("data/input.txt").unwrap();
+            assert_eq!(Some(1), p.memory_at(0));
+            assert_eq!(Some(0), p.memory_at(p.memory.len() - 1));
+            assert!(matches!(Program::load_from_file("data/missing.txt"), Err(Error::Io(_))));
+        }
+
         #[test]
         fn memory_at_out_of_range() {
             let p = Program::load(vec![0, 1, 2, 3]);
             assert_eq!(None, p.memory_at(4))
         }
 
+        #[test]
+        fn set_memory_at_last_address() {
+            let mut p = Program::load(vec![99]);
+            p.set_memory_at(usize::MAX, 5);
+            assert_eq!(5, p.memory().get(usize::MAX));
+            assert_eq!(Ok(Status::Halted), p.run());
+        }
+
         #[test]
         fn step_add_once_at_start() {
             let mut p = Program::load(vec![1, 0, 0, 0]);
-            assert_eq!(Ok(false), p.step());
-            assert_eq!(vec![2, 0, 0, 0], p.memory);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![2, 0, 0, 0], p.memory.to_vec());
             assert_eq!(4, p.pc);
         }
 
         #[test]
         fn step_add_twice() {
             let mut p = Program::load(vec![1, 0, 0, 0, 1, 0, 0, 0]);
-            assert_eq!(Ok(false), p.step());
-            assert_eq!(vec![2, 0, 0, 0, 1, 0, 0, 0], p.memory);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![2, 0, 0, 0, 1, 0, 0, 0], p.memory.to_vec());
             assert_eq!(4, p.pc);
-            assert_eq!(Ok(false), p.step());
-            assert_eq!(vec![4, 0, 0, 0, 1, 0, 0, 0], p.memory);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![4, 0, 0, 0, 1, 0, 0, 0], p.memory.to_vec());
             assert_eq!(8, p.pc);
         }
 
         #[test]
         fn step_mul_once_at_start() {
             let mut p = Program::load(vec![2, 0, 0, 0]);
-            assert_eq!(Ok(false), p.step());
-            assert_eq!(vec![4, 0, 0, 0], p.memory);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![4, 0, 0, 0], p.memory.to_vec());
             assert_eq!(4, p.pc);
         }
 
         #[test]
         fn step_mul_twice() {
             let mut p = Program::load(vec![2, 0, 0, 0, 2, 0, 0, 0]);
-            assert_eq!(Ok(false), p.step());
-            assert_eq!(vec![4, 0, 0, 0, 2, 0, 0, 0], p.memory);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![4, 0, 0, 0, 2, 0, 0, 0], p.memory.to_vec());
             assert_eq!(4, p.pc);
-            assert_eq!(Ok(false), p.step());
-            assert_eq!(vec![16, 0, 0, 0, 2, 0, 0, 0], p.memory);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![16, 0, 0, 0, 2, 0, 0, 0], p.memory.to_vec());
             assert_eq!(8, p.pc);
         }
 
         #[test]
         fn step_hlt() {
             let mut p = Program::load(vec![99]);
-            assert_eq!(Ok(true), p.step());
+            assert_eq!(Ok(Status::Halted), p.step());
             assert_eq!(0, p.pc);
-            assert_eq!(Ok(true), p.step());
+            assert_eq!(Ok(Status::Halted), p.step());
             assert_eq!(0, p.pc);
         }
 
         #[test]
         fn step_hlt_after_add() {
             let mut p = Program::load(vec![1, 0, 0, 0, 99]);
-            assert_eq!(Ok(false), p.step());
-            assert_eq!(vec![2, 0, 0, 0, 99], p.memory);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![2, 0, 0, 0, 99], p.memory.to_vec());
             assert_eq!(4, p.pc);
-            assert_eq!(Ok(true), p.step());
+            assert_eq!(Ok(Status::Halted), p.step());
             assert_eq!(4, p.pc);
-            assert_eq!(Ok(true), p.step());
+            assert_eq!(Ok(Status::Halted), p.step());
         }
 
         #[test]
         fn run_hlt_after_two_adds() {
             let mut p = Program::load(vec![1, 0, 0, 0, 1, 0, 0, 0, 99]);
-            assert_eq!(Ok(true), p.run());
-            assert_eq!(vec![4, 0, 0, 0, 1, 0, 0, 0, 99], p.memory);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![4, 0, 0, 0, 1, 0, 0, 0, 99], p.memory.to_vec());
             assert_eq!(8, p.pc);
         }
 
@@ -250,29 +885,414 @@ mod tests {
         #[test]
         fn example1() {
             let mut p = Program::load(vec![1, 0, 0, 0, 99]);
-            assert_eq!(Ok(true), p.run());
-            assert_eq!(vec![2, 0, 0, 0, 99], p.memory);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![2, 0, 0, 0, 99], p.memory.to_vec());
         }
 
         #[test]
         fn example2() {
             let mut p = Program::load(vec![2, 3, 0, 3, 99]);
-            assert_eq!(Ok(true), p.run());
-            assert_eq!(vec![2, 3, 0, 6, 99], p.memory);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![2, 3, 0, 6, 99], p.memory.to_vec());
         }
 
         #[test]
         fn example3() {
             let mut p = Program::load(vec![2, 4, 4, 5, 99, 0]);
-            assert_eq!(Ok(true), p.run());
-            assert_eq!(vec![2, 4, 4, 5, 99, 9801], p.memory);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![2, 4, 4, 5, 99, 9801], p.memory.to_vec());
         }
 
         #[test]
         fn example4() {
             let mut p = Program::load(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
-            assert_eq!(Ok(true), p.run());
-            assert_eq!(vec![30, 1, 1, 4, 2, 5, 6, 0, 99], p.memory);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![30, 1, 1, 4, 2, 5, 6, 0, 99], p.memory.to_vec());
+        }
+
+        #[test]
+        fn decode_position_modes() {
+            let inst = Instruction::decode(0, 2).unwrap();
+            assert_eq!(OP_MUL, inst.op);
+            assert_eq!([Mode::Position; 3], inst.modes);
+        }
+
+        #[test]
+        fn decode_mixed_modes() {
+            let inst = Instruction::decode(0, 21002).unwrap();
+            assert_eq!(OP_MUL, inst.op);
+            assert_eq!([Mode::Position, Mode::Immediate, Mode::Relative], inst.modes);
+        }
+
+        #[test]
+        fn decode_unknown_mode() {
+            assert_eq!(
+                Err(Error::BadMode { pc: 3, instruction: 301, param: 1 }),
+                Instruction::decode(3, 301),
+            );
+            assert_eq!(
+                Err(Error::BadMode { pc: 0, instruction: 100001, param: 4 }),
+                Instruction::decode(0, 100001),
+            );
+            assert_eq!(
+                Err(Error::UnknownOpcode { pc: 0, instruction: -1 }),
+                Instruction::decode(0, -1),
+            );
+        }
+
+        #[test]
+        fn step_mul_immediate() {
+            let mut p = Program::load(vec![1002, 4, 3, 4, 33]);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![1002, 4, 3, 4, 99], p.memory.to_vec());
+            assert_eq!(4, p.pc);
+        }
+
+        #[test]
+        fn step_add_relative() {
+            let mut p = Program::load(vec![2201, 0, 1, 6, 99, 7, 0]);
+            p.relative_base = 5;
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![2201, 0, 1, 6, 99, 7, 7], p.memory.to_vec());
+        }
+
+        #[test]
+        fn step_write_immediate() {
+            let mut p = Program::load(vec![10001, 0, 0, 0]);
+            assert_eq!(Err(Error::BadMode { pc: 0, instruction: 10001, param: 3 }), p.step());
+        }
+
+        #[test]
+        fn step_dest_grows_memory() {
+            let mut p = Program::load(vec![1, 0, 0, 6]);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![1, 0, 0, 6, 0, 0, 2], p.memory.to_vec());
+        }
+
+        #[test]
+        fn step_negative_address() {
+            let mut p = Program::load(vec![1, -1, 0, 0]);
+            assert_eq!(Err(Error::ReadOutOfBounds { pc: 0, instruction: 1, address: -1 }), p.step());
+            let mut p = Program::load(vec![20001, 0, 0, -1]);
+            assert_eq!(Err(Error::WriteOutOfBounds { pc: 0, instruction: 20001, address: -1 }), p.step());
+        }
+
+        #[test]
+        fn step_add_overflow() {
+            let mut p = Program::load(vec![1101, Value::MAX, 1, 0]);
+            assert_eq!(Err(Error::Overflow { pc: 0, instruction: 1101, a: Value::MAX, b: 1 }), p.step());
+            assert_eq!(0, p.pc);
+        }
+
+        #[test]
+        fn step_mul_overflow() {
+            let mut p = Program::load(vec![1102, Value::MIN, -1, 0]);
+            assert_eq!(Err(Error::Overflow { pc: 0, instruction: 1102, a: Value::MIN, b: -1 }), p.step());
+            assert_eq!(0, p.pc);
+        }
+
+        #[test]
+        fn write_far_address() {
+            let mut p = Program::load(vec![1101, 3, 4, 1 << 40, 4, 1 << 40, 99]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![7], p.take_output());
+        }
+
+        #[test]
+        fn example_negative_values() {
+            let mut p = Program::load(vec![1101, 100, -1, 4, 0]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(Some(99), p.memory_at(4));
+        }
+
+        #[test]
+        fn example_quine() {
+            let code = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+            let mut p = Program::load(code.clone());
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(code, p.take_output());
+        }
+
+        #[test]
+        fn example_large_product() {
+            let mut p = Program::load(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![1219070632396864], p.take_output());
+        }
+
+        #[test]
+        fn example_large_value() {
+            let mut p = Program::load(vec![104, 1125899906842624, 99]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![1125899906842624], p.take_output());
+        }
+
+        #[test]
+        fn echo_input() {
+            let mut p = Program::load(vec![3, 0, 4, 0, 99]);
+            p.push_input(42);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![42], p.take_output());
+            assert_eq!(Vec::<Value>::new(), p.take_output());
+        }
+
+        #[test]
+        fn step_in_relative() {
+            let mut p = Program::load(vec![203, 1, 99, 0]);
+            p.relative_base = 2;
+            p.push_input(7);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![203, 1, 99, 7], p.memory.to_vec());
+            assert_eq!(2, p.pc);
+        }
+
+        #[test]
+        fn step_out_immediate() {
+            let mut p = Program::load(vec![104, 13, 99]);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(vec![13], p.take_output());
+        }
+
+        #[test]
+        fn run_waits_for_input() {
+            let mut p = Program::load(vec![3, 9, 3, 10, 1, 9, 10, 11, 99, 0, 0, 0]);
+            p.push_input(2);
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+            assert_eq!(2, p.pc);
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+            p.push_input(3);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(Some(5), p.memory_at(11));
+        }
+
+        #[test]
+        fn run_input_exhausted() {
+            let mut p = Program::load(vec![3, 0, 3, 0, 99]);
+            p.set_input(io::FromIter::new(vec![1]));
+            assert_eq!(Err(Error::InputExhausted { pc: 2, instruction: 3 }), p.run());
+            assert_eq!(2, p.pc);
+        }
+
+        #[test]
+        fn run_with_source_and_sink() {
+            let mut p = Program::load(vec![3, 0, 4, 0, 3, 0, 4, 0, 99]);
+            let output = io::Queue::new();
+            p.push_input(1);
+            p.set_input(io::FromIter::new(vec![2]));
+            p.set_output(output.clone());
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![1, 2], output.drain());
+            assert_eq!(Vec::<Value>::new(), p.take_output());
+        }
+
+        fn run_with_input(code: Vec<Value>, input: Value) -> Vec<Value> {
+            let mut p = Program::load(code);
+            p.push_input(input);
+            assert_eq!(Ok(Status::Halted), p.run());
+            p.take_output()
+        }
+
+        #[test]
+        fn step_jt() {
+            let mut p = Program::load(vec![1105, 1, 7, 1105, 0, 0, 99, 99]);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(7, p.pc);
+
+            p.pc = 3;
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(6, p.pc);
+        }
+
+        #[test]
+        fn step_jf() {
+            let mut p = Program::load(vec![1106, 0, 7, 1106, 1, 0, 99, 99]);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(7, p.pc);
+
+            p.pc = 3;
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(6, p.pc);
+        }
+
+        #[test]
+        fn step_lt() {
+            let mut p = Program::load(vec![1107, 1, 2, 9, 1107, 2, 1, 10, 99, 5, 5]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(Some(1), p.memory_at(9));
+            assert_eq!(Some(0), p.memory_at(10));
+        }
+
+        #[test]
+        fn step_eq() {
+            let mut p = Program::load(vec![1108, 3, 3, 9, 1108, 3, 4, 10, 99, 5, 5]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(Some(1), p.memory_at(9));
+            assert_eq!(Some(0), p.memory_at(10));
+        }
+
+        #[test]
+        fn step_arb() {
+            let mut p = Program::load(vec![109, 5, 9, 0, 99]);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(5, p.relative_base);
+            assert_eq!(2, p.pc);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(114, p.relative_base);
+        }
+
+        #[test]
+        fn example_eq_position() {
+            let code = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, 0, 8];
+            assert_eq!(vec![1], run_with_input(code.clone(), 8));
+            assert_eq!(vec![0], run_with_input(code, 7));
+        }
+
+        #[test]
+        fn example_lt_position() {
+            let code = vec![3, 9, 7, 9, 10, 9, 4, 9, 99, 0, 8];
+            assert_eq!(vec![1], run_with_input(code.clone(), 7));
+            assert_eq!(vec![0], run_with_input(code, 8));
+        }
+
+        #[test]
+        fn example_eq_immediate() {
+            let code = vec![3, 3, 1108, 0, 8, 3, 4, 3, 99];
+            assert_eq!(vec![1], run_with_input(code.clone(), 8));
+            assert_eq!(vec![0], run_with_input(code, 9));
+        }
+
+        #[test]
+        fn example_lt_immediate() {
+            let code = vec![3, 3, 1107, 0, 8, 3, 4, 3, 99];
+            assert_eq!(vec![1], run_with_input(code.clone(), 3));
+            assert_eq!(vec![0], run_with_input(code, 8));
+        }
+
+        #[test]
+        fn example_jump_position() {
+            let code = vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, 0, 0, 1, 9];
+            assert_eq!(vec![0], run_with_input(code.clone(), 0));
+            assert_eq!(vec![1], run_with_input(code, 5));
+        }
+
+        #[test]
+        fn example_jump_immediate() {
+            let code = vec![3, 3, 1105, 0, 9, 1101, 0, 0, 12, 4, 12, 99, 1];
+            assert_eq!(vec![0], run_with_input(code.clone(), 0));
+            assert_eq!(vec![1], run_with_input(code, 5));
+        }
+
+        #[test]
+        fn example_compare_to_eight() {
+            let code = vec![
+                3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31,
+                1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104,
+                999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99,
+            ];
+            assert_eq!(vec![999], run_with_input(code.clone(), 7));
+            assert_eq!(vec![1000], run_with_input(code.clone(), 8));
+            assert_eq!(vec![1001], run_with_input(code, 9));
+        }
+
+        #[test]
+        fn example_relative_output() {
+            let mut p = Program::load(vec![109, 5, 204, 1, 99, 0, 42]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            assert_eq!(vec![42], p.take_output());
+        }
+
+        #[test]
+        fn step_unknown_opcode() {
+            let mut p = Program::load(vec![1, 0, 0, 0, 42]);
+            assert_eq!(Err(Error::UnknownOpcode { pc: 4, instruction: 42 }), p.run());
+        }
+
+        #[test]
+        fn step_jump_out_of_bounds() {
+            let mut p = Program::load(vec![1105, 1, -5]);
+            assert_eq!(Err(Error::JumpOutOfBounds { pc: 0, instruction: 1105, target: -5 }), p.step());
+        }
+
+        #[test]
+        fn call_unknown_opcode() {
+            let mut p = Program::load(vec![1, 0, 0, 4, 99, 10, 20]);
+            assert_eq!(Err(Error::UnknownOpcode { pc: 4, instruction: 30 }), p.call(5, 6));
+        }
+
+        #[test]
+        fn op_lookup() {
+            assert_eq!(Some("MUL"), op_info(OP_MUL).map(|info| info.mnemonic));
+            assert_eq!(None, op_info(42));
+            assert_eq!(Some(OP_ARB), op_by_mnemonic("arb").map(|info| info.op));
+            assert_eq!(None, op_by_mnemonic("NOP"));
+        }
+
+        #[test]
+        fn last_write() {
+            let mut p = Program::load(vec![1101, 1, 2, 7, 104, 0, 99, 0]);
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(Some(7), p.last_write());
+            assert_eq!(Ok(Status::Running), p.step());
+            assert_eq!(None, p.last_write());
+        }
+
+        #[test]
+        fn step_budget() {
+            let mut p = Program::load(vec![1105, 1, 0]);
+            p.set_budget(budget::Budget { steps: Some(10), ..Default::default() });
+            assert_eq!(Err(Error::StepLimit { pc: 0, limit: 10 }), p.run());
+            assert_eq!(10, p.steps());
+            p.reset();
+            assert_eq!(Err(Error::StepLimit { pc: 0, limit: 10 }), p.run());
+
+            // enough budget to finish
+            let mut p = Program::load(vec![1101, 1, 2, 0, 99]);
+            p.set_budget(budget::Budget { steps: Some(2), ..Default::default() });
+            assert_eq!(Ok(Status::Halted), p.run());
+        }
+
+        #[test]
+        fn memory_budget() {
+            let mut p = Program::load(vec![3, 100, 1101, 1, 2, 1000, 99]);
+            p.set_budget(budget::Budget { memory: Some(1000), ..Default::default() });
+            p.push_input(5);
+            assert_eq!(Err(Error::MemoryLimit { pc: 2, instruction: 1101, address: 1000 }), p.run());
+            assert_eq!(5, p.memory().get(100));
+
+            // input isn't consumed by a write that would fail
+            let mut p = Program::load(vec![3, 1000, 99]);
+            p.set_budget(budget::Budget { memory: Some(1000), ..Default::default() });
+            p.push_input(5);
+            assert_eq!(Err(Error::MemoryLimit { pc: 0, instruction: 3, address: 1000 }), p.run());
+            assert_eq!(vec![5], p.snapshot().inputs);
+        }
+
+        #[test]
+        fn loop_detection() {
+            let detect = budget::Budget { detect_loops: true, ..Default::default() };
+
+            let mut p = Program::load(vec![1105, 1, 0]);
+            p.set_budget(detect);
+            assert_eq!(Err(Error::Loop { pc: 0, period: 1 }), p.run());
+
+            // a two instruction loop that keeps writing the same value
+            let mut p = Program::load(vec![1101, 1, 2, 7, 1105, 1, 0, 0]);
+            p.set_budget(detect);
+            assert_eq!(Err(Error::Loop { pc: 4, period: 2 }), p.run());
+
+            // a counter changes memory every time round, so it isn't a loop
+            // even though it never halts
+            let mut p = Program::load(vec![101, 1, 7, 7, 1105, 1, 0, 0]);
+            p.set_budget(budget::Budget { steps: Some(1000), detect_loops: true, ..Default::default() });
+            assert_eq!(Err(Error::StepLimit { pc: 0, limit: 1000 }), p.run());
+
+            // nor is going round again after reading input
+            let mut p = Program::load(vec![3, 7, 1105, 1, 0, 0, 0, 0]);
+            p.set_budget(detect);
+            for _ in 0..10 {
+                p.push_input(1);
+            }
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
         }
     }
 }
\ No newline at end of file
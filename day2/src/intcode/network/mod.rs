@@ -0,0 +1,403 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+
+use super::{Program, Status, Value};
+
+// Value a node reads when no packet is waiting for it.
+const NO_PACKET: Value = -1;
+
+// How many instructions a node may run per round before yielding.
+const QUANTUM: u64 = 10_000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Packet {
+    pub src: usize,
+    pub dest: Value,
+    pub x: Value,
+    pub y: Value,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct NatConfig {
+    pub address: Value,
+    pub enabled: bool,
+    // How many idle rounds in a row before the NAT wakes node 0 up.
+    pub idle_rounds: usize,
+}
+
+impl Default for NatConfig {
+    fn default() -> NatConfig {
+        NatConfig {
+            address: 255,
+            enabled: true,
+            idle_rounds: 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Event {
+    // A packet was delivered to a node's queue.
+    Sent(Packet),
+    // A packet addressed to nobody was dropped.
+    Dropped(Packet),
+    // The NAT took a packet, replacing the one it held.
+    NatReceived(Packet),
+    // The network went idle and the NAT sent its packet to node 0.
+    NatSent(Packet),
+    // The network went idle with nothing for the NAT to send.
+    Idle,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    Node { address: usize, error: super::Error },
+    // Every node halted, so nothing will ever happen again.
+    Halted,
+    // `run_until` gave up before seeing the event it was waiting for.
+    RoundLimit(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Node { address, error } => write!(f, "node {}: {}", address, error),
+            Error::Halted => write!(f, "every node halted"),
+            Error::RoundLimit(rounds) => write!(f, "gave up after {} rounds", rounds),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+type Observer = Box<dyn FnMut(&Event)>;
+
+// A set of intcode machines exchanging (dest, x, y) packets. Every round each
+// node gets its next queued packet, or -1 if it's waiting for input with
+// nothing queued, then runs until it waits for input again or uses up its
+// quantum. Whatever it sent is routed at the end of its turn.
+pub struct Network {
+    nodes: Vec<Program>,
+    // How each node's last turn ended. Nodes start out waiting for their
+    // first packet.
+    status: Vec<Status>,
+    queues: Vec<VecDeque<(Value, Value)>>,
+    nat: NatConfig,
+    nat_packet: Option<Packet>,
+    idle: usize,
+    rounds: u64,
+    observers: Vec<Observer>,
+}
+
+impl Network {
+    // Boot `count` copies of `code`, each given its address as first input.
+    pub fn new(code: &[Value], count: usize, nat: NatConfig) -> Network {
+        let nodes = (0..count)
+            .map(|address| {
+                let mut node = Program::load(code.to_vec());
+                node.push_input(address as Value);
+                node
+            })
+            .collect();
+        Network {
+            nodes,
+            status: vec![Status::WaitingForInput; count],
+            queues: vec![VecDeque::new(); count],
+            nat,
+            nat_packet: None,
+            idle: 0,
+            rounds: 0,
+            observers: Vec::new(),
+        }
+    }
+
+    // Call `observer` for every event from now on.
+    pub fn observe<F: FnMut(&Event) + 'static>(&mut self, observer: F) {
+        self.observers.push(Box::new(observer))
+    }
+
+    pub fn node(&self, address: usize) -> &Program {
+        &self.nodes[address]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn rounds(&self) -> u64 {
+        self.rounds
+    }
+
+    // The packet the NAT is currently holding.
+    pub fn nat_packet(&self) -> Option<Packet> {
+        self.nat_packet
+    }
+
+    // Queue a packet for a node as if another node had sent it.
+    pub fn send(&mut self, dest: usize, x: Value, y: Value) {
+        self.queues[dest].push_back((x, y))
+    }
+
+    fn emit(&mut self, event: Event, events: &mut Vec<Event>) {
+        for observer in self.observers.iter_mut() {
+            observer(&event);
+        }
+        events.push(event);
+    }
+
+    fn route(&mut self, packet: Packet, events: &mut Vec<Event>) {
+        match packet.dest {
+            d if d >= 0 && (d as usize) < self.nodes.len() => {
+                self.queues[d as usize].push_back((packet.x, packet.y));
+                self.emit(Event::Sent(packet), events);
+            }
+            d if self.nat.enabled && d == self.nat.address => {
+                self.nat_packet = Some(packet);
+                self.emit(Event::NatReceived(packet), events);
+            }
+            _ => self.emit(Event::Dropped(packet), events),
+        }
+    }
+
+    // Run a single round and return what happened during it.
+    pub fn round(&mut self) -> Result<Vec<Event>, Error> {
+        let mut events = Vec::new();
+        let mut busy = false;
+
+        for address in 0..self.nodes.len() {
+            if self.status[address] == Status::Halted {
+                continue;
+            }
+
+            // a node still busy from last round hasn't read the last -1 yet,
+            // so it doesn't get another
+            let node = &mut self.nodes[address];
+            match self.queues[address].pop_front() {
+                Some((x, y)) => {
+                    node.push_input(x);
+                    node.push_input(y);
+                    busy = true;
+                }
+                None if self.status[address] == Status::WaitingForInput => node.push_input(NO_PACKET),
+                None => (),
+            }
+
+            let start = node.steps();
+            let mut status = Status::Running;
+            while status == Status::Running && node.steps() - start < QUANTUM {
+                status = node.step().map_err(|error| Error::Node { address, error })?;
+            }
+            self.status[address] = status;
+            busy |= status == Status::Running;
+
+            // a partially written packet stays in the buffer until the rest
+            // of it shows up
+            let mut outputs = self.nodes[address].take_output();
+            let partial = outputs.len() % 3;
+            for value in outputs.split_off(outputs.len() - partial) {
+                self.nodes[address].push_output(value);
+            }
+            for chunk in outputs.chunks(3) {
+                busy = true;
+                self.route(Packet { src: address, dest: chunk[0], x: chunk[1], y: chunk[2] }, &mut events);
+            }
+        }
+        self.rounds += 1;
+
+        // what happened on the way to halting is still reported, the error
+        // comes from the next round
+        if self.status.iter().all(|s| *s == Status::Halted) {
+            return match events.is_empty() {
+                true => Err(Error::Halted),
+                false => Ok(events),
+            };
+        }
+
+        let idle = !busy && self.queues.iter().all(|q| q.is_empty());
+        self.idle = match idle {
+            true => self.idle + 1,
+            false => 0,
+        };
+        if self.idle >= self.nat.idle_rounds.max(1) {
+            self.idle = 0;
+            match self.nat_packet {
+                Some(packet) if self.nat.enabled && !self.nodes.is_empty() => {
+                    self.queues[0].push_back((packet.x, packet.y));
+                    let sent = Packet { src: packet.src, dest: 0, x: packet.x, y: packet.y };
+                    self.emit(Event::NatSent(sent), &mut events);
+                }
+                _ => self.emit(Event::Idle, &mut events),
+            }
+        }
+
+        Ok(events)
+    }
+
+    // Run rounds until `done` returns true for an event, and return that
+    // event. Gives up after `max_rounds` rounds.
+    pub fn run_until<F: FnMut(&Event) -> bool>(&mut self, mut done: F, max_rounds: u64) -> Result<Event, Error> {
+        for _ in 0..max_rounds {
+            for event in self.round()? {
+                if done(&event) {
+                    return Ok(event);
+                }
+            }
+        }
+        Err(Error::RoundLimit(max_rounds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod network {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        use super::super::*;
+        use super::super::super::asm;
+
+        // A packet (x, y) means "send (x + 1, y - 1) on to node x", until y
+        // runs out and the node reports to the NAT instead. Node 0 starts the
+        // chain by sending (2, 3) to node 1.
+        const RELAY: &str = "
+                    IN [addr]
+                    JT [addr], loop
+                    OUT 1
+                    OUT 2
+                    OUT 3
+            loop:   IN [x]
+                    EQ [x], -1, [tmp]
+                    JT [tmp], loop
+                    IN [y]
+                    JT [y], forward
+                    OUT 255
+                    OUT [addr]
+                    OUT 0
+                    JT 1, loop
+            forward:
+                    OUT [x]
+                    ADD [x], 1, [x]
+                    OUT [x]
+                    ADD [y], -1, [y]
+                    OUT [y]
+                    JT 1, loop
+            addr:   .data 0
+            tmp:    .data 0
+            x:      .data 0
+            y:      .data 0
+        ";
+
+        fn relay(count: usize, nat: NatConfig) -> Network {
+            Network::new(&asm::assemble(RELAY).unwrap().code, count, nat)
+        }
+
+        fn packet(src: usize, dest: Value, x: Value, y: Value) -> Packet {
+            Packet { src, dest, x, y }
+        }
+
+        #[test]
+        fn routing() {
+            let mut n = relay(50, NatConfig::default());
+            let seen = Rc::new(RefCell::new(Vec::new()));
+            let observed = seen.clone();
+            n.observe(move |event| observed.borrow_mut().push(*event));
+
+            let event = n.run_until(|event| matches!(event, Event::NatReceived(_)), 20).unwrap();
+            assert_eq!(Event::NatReceived(packet(4, 255, 4, 0)), event);
+            assert_eq!(
+                vec![
+                    Event::Sent(packet(0, 1, 2, 3)),
+                    Event::Sent(packet(1, 2, 3, 2)),
+                    Event::Sent(packet(2, 3, 4, 1)),
+                    Event::Sent(packet(3, 4, 5, 0)),
+                    Event::NatReceived(packet(4, 255, 4, 0)),
+                ],
+                *seen.borrow(),
+            );
+            assert_eq!(Some(packet(4, 255, 4, 0)), n.nat_packet());
+        }
+
+        #[test]
+        fn nat_wakes_node_zero() {
+            let mut n = relay(5, NatConfig::default());
+            let mut sent = Vec::new();
+            // stop once the NAT sends the same y twice in a row
+            let event = n.run_until(
+                |event| match event {
+                    Event::NatSent(p) => {
+                        let repeat = sent.last() == Some(&p.y);
+                        sent.push(p.y);
+                        repeat
+                    }
+                    _ => false,
+                },
+                100,
+            ).unwrap();
+            assert_eq!(Event::NatSent(packet(0, 0, 0, 0)), event);
+            assert_eq!(vec![0, 0], sent);
+        }
+
+        #[test]
+        fn idle_without_nat() {
+            let nat = NatConfig { enabled: false, ..NatConfig::default() };
+            let mut n = relay(5, nat);
+            let event = n.run_until(|event| matches!(event, Event::Dropped(_) | Event::Idle), 20).unwrap();
+            assert_eq!(Event::Dropped(packet(4, 255, 4, 0)), event);
+            assert_eq!(Ok(Event::Idle), n.run_until(|event| *event == Event::Idle, 20));
+        }
+
+        #[test]
+        fn dropped_and_injected() {
+            let mut n = relay(3, NatConfig::default());
+            // node 1 forwards to 7, which doesn't exist
+            n.send(1, 7, 2);
+            let event = n.run_until(|event| matches!(event, Event::Dropped(_)), 20).unwrap();
+            assert_eq!(Event::Dropped(packet(1, 7, 8, 1)), event);
+        }
+
+        #[test]
+        fn busy_nodes_dont_pile_up_empty_reads() {
+            // spends a couple of rounds counting before it reads anything
+            let code = asm::assemble("
+                        IN [addr]
+                loop:   ADD [n], 1, [n]
+                        LT [n], 10000, [t]
+                        JT [t], loop
+                read:   IN [n]
+                        JT 1, read
+                addr:   .data 0
+                n:      .data 0
+                t:      .data 0
+            ").unwrap().code;
+            let mut n = Network::new(&code, 1, NatConfig::default());
+            n.round().unwrap();
+            n.round().unwrap();
+            assert_eq!(vec![NO_PACKET], n.node(0).snapshot().inputs);
+        }
+
+        #[test]
+        fn errors() {
+            let mut n = Network::new(&[3, 0, 99], 2, NatConfig::default());
+            assert_eq!(Err(Error::Halted), n.round());
+
+            // a packet sent just before halting isn't lost
+            let mut n = Network::new(&[3, 0, 104, 0, 104, 5, 104, 6, 99], 1, NatConfig::default());
+            assert_eq!(Ok(vec![Event::Sent(packet(0, 0, 5, 6))]), n.round());
+            assert_eq!(Err(Error::Halted), n.round());
+
+            let mut n = Network::new(&[3, 0, 42], 2, NatConfig::default());
+            assert_eq!(
+                Err(Error::Node { address: 0, error: super::super::super::Error::UnknownOpcode { pc: 2, instruction: 42 } }),
+                n.round(),
+            );
+
+            let mut n = relay(2, NatConfig::default());
+            assert_eq!(Err(Error::RoundLimit(3)), n.run_until(|_| false, 3));
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::io::{BufRead, BufReader, Read};
+
+use super::{Error, Value};
+
+// A value being collected, with where it started (1-based).
+struct Token {
+    text: Vec<u8>,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn finish(self) -> Result<Value, Error> {
+        let text = String::from_utf8_lossy(&self.text);
+        let text = text.trim_end();
+        match text.parse::<Value>() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(Error::Parse { line: self.line, column: self.column, token: text.to_string() }),
+        }
+    }
+}
+
+// Read comma separated program text from `input` without holding more than
+// the value being parsed. Whitespace and newlines can go anywhere between
+// values, empty items and a trailing comma are skipped, and '#' starts a
+// comment running to the end of the line.
+pub fn parse<R: Read>(input: R) -> Result<Vec<Value>, Error> {
+    let mut input = BufReader::new(input);
+    let mut code = Vec::new();
+    let mut token: Option<Token> = None;
+    let mut comment = false;
+    let (mut line, mut column) = (1, 0);
+
+    loop {
+        let buf = match input.fill_buf() {
+            Ok([]) => break,
+            Ok(buf) => buf,
+            Err(e) => return Err(Error::Io(e.to_string())),
+        };
+        for &byte in buf {
+            // count characters rather than bytes for the column
+            if byte & 0xc0 != 0x80 {
+                column += 1;
+            }
+            match byte {
+                b'\n' => {
+                    line += 1;
+                    column = 0;
+                    comment = false;
+                    if let Some(token) = &mut token {
+                        token.text.push(b' ');
+                    }
+                }
+                _ if comment => (),
+                b'#' => comment = true,
+                b',' => code.extend(token.take().map(Token::finish).transpose()?),
+                b if b.is_ascii_whitespace() && token.is_none() => (),
+                b => token.get_or_insert(Token { text: Vec::new(), line, column }).text.push(b),
+            }
+        }
+        let len = buf.len();
+        input.consume(len);
+    }
+    code.extend(token.map(Token::finish).transpose()?);
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    mod parse {
+        use super::super::*;
+        use std::io;
+
+        fn error(text: &str) -> Error {
+            parse(text.as_bytes()).unwrap_err()
+        }
+
+        #[test]
+        fn values() {
+            assert_eq!(Ok(vec![1, -2, 3]), parse("1,-2,3".as_bytes()));
+            assert_eq!(Ok(vec![1, 2, 3]), parse(" 1 ,\n\t2,\r\n3 ,\n".as_bytes()));
+            assert_eq!(Ok(vec![1, 2]), parse("1,,2,".as_bytes()));
+            assert_eq!(Ok(Vec::new()), parse("".as_bytes()));
+            assert_eq!(Ok(vec![Value::MIN]), parse("-9223372036854775808".as_bytes()));
+        }
+
+        #[test]
+        fn comments() {
+            let text = "# header, with commas\n1,0,0,3, # add\n99 # halt\n# trailing";
+            assert_eq!(Ok(vec![1, 0, 0, 3, 99]), parse(text.as_bytes()));
+        }
+
+        #[test]
+        fn errors() {
+            assert_eq!(Error::Parse { line: 1, column: 3, token: String::from("x") }, error("1,x,3"));
+            assert_eq!(Error::Parse { line: 2, column: 4, token: String::from("5a") }, error("1,\n 2,5a"));
+            // a missing comma leaves two numbers in one token
+            assert_eq!(Error::Parse { line: 1, column: 1, token: String::from("1 2") }, error("1 2,3"));
+            assert_eq!(Error::Parse { line: 1, column: 1, token: String::from("1  2") }, error("1\n 2"));
+            let overflow = "99999999999999999999";
+            assert_eq!(Error::Parse { line: 1, column: 6, token: String::from(overflow) }, error(&format!("1, 2,{}", overflow)));
+            // columns count characters
+            assert_eq!(Error::Parse { line: 1, column: 3, token: String::from("é") }, error("1,é"));
+            assert_eq!("invalid value \"x\" at line 1, column 3", error("1,x").to_string());
+        }
+
+        #[test]
+        fn streams() {
+            // one byte at a time, so values span reads
+            struct Trickle<'a>(&'a [u8]);
+
+            impl Read for Trickle<'_> {
+                fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                    match self.0.split_first() {
+                        Some((first, rest)) if !buf.is_empty() => {
+                            buf[0] = *first;
+                            self.0 = rest;
+                            Ok(1)
+                        }
+                        _ => Ok(0),
+                    }
+                }
+            }
+
+            assert_eq!(Ok(vec![1002, -34, 5]), parse(Trickle(b"1002,-34,\n5")));
+            assert_eq!(Error::Parse { line: 2, column: 2, token: String::from("x") }, parse(Trickle(b"1,\n x")).unwrap_err());
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use super::trace::{Event, Tracer};
+use super::{Error, Program, Status, Value, OP_HLT, OP_IN, OP_OUT};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Input(Value),
+    Output(Value),
+    Halt,
+}
+
+// Something the program did that a session records, stamped with the number
+// of instructions that ran before it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Entry {
+    pub step: u64,
+    pub kind: Kind,
+}
+
+impl Entry {
+    // The entry for a traced instruction, if it's one a session records.
+    // What IN writes is the value it consumed, wherever it landed.
+    pub fn from_event(event: &Event) -> Option<Entry> {
+        let kind = match event.instruction.op {
+            OP_IN => Kind::Input(event.write?.1),
+            OP_OUT => Kind::Output(*event.reads.first()?),
+            OP_HLT => Kind::Halt,
+            _ => return None,
+        };
+        Some(Entry { step: event.step, kind })
+    }
+}
+
+// One line of a session file.
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::Input(value) => write!(f, "{} in {}", self.step, value),
+            Kind::Output(value) => write!(f, "{} out {}", self.step, value),
+            Kind::Halt => write!(f, "{} halt", self.step),
+        }
+    }
+}
+
+// Everything a run consumed and produced, in order. In the file form each
+// entry is a line; blank lines and lines starting with '#' are skipped.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Session {
+    pub entries: Vec<Entry>,
+}
+
+fn invalid(line: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+impl Session {
+    pub fn read<R: BufRead>(input: R) -> io::Result<Session> {
+        let mut session = Session::default();
+        for (idx, line) in input.lines().enumerate() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() || words[0].starts_with('#') {
+                continue;
+            }
+            let step = words[0].parse().map_err(|_| invalid(idx + 1, "bad step"))?;
+            let value = |word: Option<&&str>| match word.map(|w| w.parse()) {
+                Some(Ok(value)) if words.len() == 3 => Ok(value),
+                _ => Err(invalid(idx + 1, "bad value")),
+            };
+            let kind = match words.get(1) {
+                Some(&"in") => Kind::Input(value(words.get(2))?),
+                Some(&"out") => Kind::Output(value(words.get(2))?),
+                Some(&"halt") if words.len() == 2 => Kind::Halt,
+                _ => return Err(invalid(idx + 1, "expected in, out or halt")),
+            };
+            session.entries.push(Entry { step, kind });
+        }
+        Ok(session)
+    }
+
+    pub fn inputs(&self) -> Vec<Value> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry.kind {
+                Kind::Input(value) => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+// A tracer that writes a session file as the program runs, so it's complete
+// up to the last instruction even if the run ends in an error. Values read
+// from devices aren't recorded, so programs using them won't replay.
+pub struct Recorder<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(out: W) -> Recorder<W> {
+        Recorder { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Tracer for Recorder<W> {
+    fn trace(&mut self, event: &Event) {
+        if let Some(entry) = Entry::from_event(event) {
+            // like the other tracers, failing to write can't stop the program
+            let _ = writeln!(self.out, "{}", entry);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Found {
+    Entry(Entry),
+    WaitingForInput,
+    Error(Error),
+}
+
+// The first point where a replay didn't do what the session says.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Divergence {
+    // Index of the session entry that was expected.
+    pub index: usize,
+    pub expected: Entry,
+    pub found: Found,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "entry {}: expected \"{}\", ", self.index + 1, self.expected)?;
+        match &self.found {
+            Found::Entry(entry) => write!(f, "found \"{}\"", entry),
+            Found::WaitingForInput => write!(f, "program is waiting for input"),
+            Found::Error(e) => write!(f, "program failed: {}", e),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Collect(Vec<Entry>);
+
+impl Tracer for Collect {
+    fn trace(&mut self, event: &Event) {
+        self.0.extend(Entry::from_event(event));
+    }
+}
+
+// Run `program` on the session's inputs, checking that everything it reads,
+// writes and when it halts match the session. Stops after the last entry, so
+// the caller can carry on from the state the recording ended in. A tracer
+// set on the program doesn't see the replay, but is back in place afterwards.
+pub fn replay(program: &mut Program, session: &Session) -> Result<(), Divergence> {
+    for value in session.inputs() {
+        program.push_input(value);
+    }
+    let seen = Rc::new(RefCell::new(Collect::default()));
+    let tracer = program.tracer.take();
+    program.set_tracer(seen.clone());
+    let result = check(program, session, &seen);
+    program.tracer = tracer;
+    result
+}
+
+fn check(program: &mut Program, session: &Session, seen: &Rc<RefCell<Collect>>) -> Result<(), Divergence> {
+    let mut index = 0;
+    while let Some(expected) = session.entries.get(index) {
+        let diverged = move |found| Divergence { index, expected: *expected, found };
+        let status = program.step().map_err(|e| diverged(Found::Error(e)))?;
+        if let Some(entry) = seen.borrow_mut().0.pop() {
+            if entry != *expected {
+                return Err(diverged(Found::Entry(entry)));
+            }
+            index += 1;
+        }
+        if status == Status::WaitingForInput {
+            return Err(diverged(Found::WaitingForInput));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    mod replay {
+        use super::super::*;
+        use super::super::super::asm::assemble;
+        use super::super::super::device::Console;
+        use super::super::super::io::FromIter;
+        use super::super::super::trace::Profile;
+
+        // echo each input doubled until a zero
+        const DOUBLER: &str = "
+        loop:   IN [x]
+                JF [x], done
+                MUL [x], 2, [x]
+                OUT [x]
+                JT 1, loop
+        done:   HLT
+        x:      .data 0
+        ";
+
+        fn doubler() -> Vec<Value> {
+            assemble(DOUBLER).unwrap().code
+        }
+
+        fn record(code: Vec<Value>, input: Vec<Value>) -> String {
+            let recorder = Rc::new(RefCell::new(Recorder::new(Vec::new())));
+            let mut p = Program::load(code);
+            p.set_input(FromIter::new(input));
+            p.set_tracer(recorder.clone());
+            assert_eq!(Ok(Status::Halted), p.run());
+            p.clear_tracer();
+            let out = Rc::try_unwrap(recorder).ok().unwrap().into_inner().into_inner();
+            String::from_utf8(out).unwrap()
+        }
+
+        fn session(text: &str) -> Session {
+            Session::read(text.as_bytes()).unwrap()
+        }
+
+        #[test]
+        fn round_trip() {
+            let text = record(doubler(), vec![1, 2, 0]);
+            assert_eq!("0 in 1\n3 out 2\n5 in 2\n8 out 4\n10 in 0\n12 halt\n", text);
+            let session = session(&text);
+            assert_eq!(Entry { step: 8, kind: Kind::Output(4) }, session.entries[3]);
+            assert_eq!(vec![1, 2, 0], session.inputs());
+            assert_eq!(text, session.to_string());
+
+            let mut p = Program::load(doubler());
+            assert_eq!(Ok(()), replay(&mut p, &session));
+            assert_eq!(vec![2, 4], p.take_output());
+            assert_eq!(13, p.steps());
+        }
+
+        #[test]
+        fn carry_on_after_the_recording() {
+            let mut p = Program::load(doubler());
+            let profile = Rc::new(RefCell::new(Profile::new()));
+            p.set_tracer(profile.clone());
+            assert_eq!(Ok(()), replay(&mut p, &session("0 in 1\n3 out 2\n")));
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+            // the caller's tracer only sees the jump back to the IN
+            assert_eq!(1, profile.borrow().steps());
+        }
+
+        #[test]
+        fn input_to_a_device() {
+            let console = Rc::new(RefCell::new(Console::new()));
+            let recorder = Rc::new(RefCell::new(Recorder::new(Vec::new())));
+            let mut p = Program::load(vec![3, 100, 99]);
+            p.attach(100..101, console.clone()).unwrap();
+            p.push_input(65);
+            p.set_tracer(recorder.clone());
+            assert_eq!(Ok(Status::Halted), p.run());
+            p.clear_tracer();
+            assert_eq!("A", console.borrow().output());
+            let text = String::from_utf8(Rc::try_unwrap(recorder).ok().unwrap().into_inner().into_inner()).unwrap();
+            assert_eq!("0 in 65\n1 halt\n", text);
+
+            let mut p = Program::load(vec![3, 100, 99]);
+            p.attach(100..101, Console::new()).unwrap();
+            assert_eq!(Ok(()), replay(&mut p, &session(&text)));
+        }
+
+        #[test]
+        fn divergence() {
+            let session = session(&record(doubler(), vec![1, 2, 0]));
+
+            // tripling instead of doubling
+            let mut p = Program::load(assemble(&DOUBLER.replace("2, [x]", "3, [x]")).unwrap().code);
+            let divergence = replay(&mut p, &session).unwrap_err();
+            assert_eq!(1, divergence.index);
+            assert_eq!(Found::Entry(Entry { step: 3, kind: Kind::Output(3) }), divergence.found);
+            assert_eq!("entry 2: expected \"3 out 2\", found \"3 out 3\"", divergence.to_string());
+
+            // inputs are fed in order, so a missing one shows up as the next
+            // being read early
+            let mut short = session.clone();
+            short.entries.remove(2);
+            let divergence = replay(&mut Program::load(doubler()), &short).unwrap_err();
+            assert_eq!(Found::Entry(Entry { step: 5, kind: Kind::Input(0) }), divergence.found);
+
+            // or as waiting when it was the last
+            let mut short = session.clone();
+            short.entries.remove(4);
+            let divergence = replay(&mut Program::load(doubler()), &short).unwrap_err();
+            assert_eq!(Divergence { index: 4, expected: short.entries[4], found: Found::WaitingForInput }, divergence);
+
+            // failing where the recording halted
+            let mut code = doubler();
+            code[14] = 98;
+            let divergence = replay(&mut Program::load(code), &session).unwrap_err();
+            assert_eq!(5, divergence.index);
+            assert_eq!(Found::Error(Error::UnknownOpcode { pc: 14, instruction: 98 }), divergence.found);
+            assert!(divergence.to_string().starts_with("entry 6: expected \"12 halt\", program failed: "));
+        }
+
+        #[test]
+        fn read() {
+            let parsed = session("# a comment\n\n  0 in -5\n1 halt\n");
+            assert_eq!(
+                vec![Entry { step: 0, kind: Kind::Input(-5) }, Entry { step: 1, kind: Kind::Halt }],
+                parsed.entries,
+            );
+            let error = |text: &str| Session::read(text.as_bytes()).unwrap_err().to_string();
+            assert_eq!("line 2: bad value", error("0 in 1\n1 out x\n"));
+            assert_eq!("line 1: bad step", error("x in 1"));
+            assert_eq!("line 1: expected in, out or halt", error("0 jump 1"));
+            assert_eq!("line 1: bad value", error("0 in 1 2"));
+            assert_eq!("line 1: expected in, out or halt", error("0 halt 1"));
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::error;
+use std::fmt;
+
+use super::{Program, Status, Value};
+
+// How many instructions a machine may run before the next one gets a turn,
+// so a machine that never blocks can't starve the others.
+const DEFAULT_QUANTUM: u64 = 10_000;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    // A machine failed while running.
+    Machine { machine: usize, error: super::Error },
+    // Every machine that hasn't halted is waiting for input nobody will send.
+    // Holds (machine, pc) for each of them.
+    Deadlock { blocked: Vec<(usize, usize)> },
+    // A link names a machine that was never added.
+    NoSuchMachine(usize),
+    // A machine with links has an output sink, which would take the output
+    // its links are waiting for.
+    OutputSink { machine: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Machine { machine, error } => write!(f, "machine {}: {}", machine, error),
+            Error::Deadlock { blocked } => write!(
+                f,
+                "deadlock, blocked machines: {}",
+                blocked.iter().map(|(m, pc)| format!("{} at pc {}", m, pc)).collect::<Vec<_>>().join(", "),
+            ),
+            Error::NoSuchMachine(id) => write!(f, "no machine {}", id),
+            Error::OutputSink { machine } => write!(f, "machine {} has links and an output sink", machine),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+// Runs several machines cooperatively, passing the output of each machine to
+// the inputs of the machines it's connected to. Output from a machine without
+// connections stays in its buffer for the caller to take. Machines with
+// connections can't have an output sink, as routing works from the buffer.
+pub struct Scheduler {
+    machines: Vec<Program>,
+    links: Vec<(usize, usize)>,
+    halted: Vec<bool>,
+    last_output: Vec<Option<Value>>,
+    quantum: u64,
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            machines: Vec::new(),
+            links: Vec::new(),
+            halted: Vec::new(),
+            last_output: Vec::new(),
+            quantum: DEFAULT_QUANTUM,
+        }
+    }
+
+    // Each machine feeds the next one.
+    pub fn pipeline(programs: Vec<Program>) -> Scheduler {
+        let mut scheduler = Scheduler::new();
+        let count = programs.len();
+        for program in programs {
+            scheduler.add(program);
+        }
+        for id in 1..count {
+            scheduler.links.push((id - 1, id));
+        }
+        scheduler
+    }
+
+    // A pipeline where the last machine feeds back into the first.
+    pub fn ring(programs: Vec<Program>) -> Scheduler {
+        let mut scheduler = Scheduler::pipeline(programs);
+        match scheduler.machines.len() {
+            0 => (),
+            count => scheduler.links.push((count - 1, 0)),
+        }
+        scheduler
+    }
+
+    pub fn set_quantum(&mut self, quantum: u64) {
+        self.quantum = quantum.max(1)
+    }
+
+    pub fn add(&mut self, program: Program) -> usize {
+        self.machines.push(program);
+        self.halted.push(false);
+        self.last_output.push(None);
+        self.machines.len() - 1
+    }
+
+    pub fn connect(&mut self, from: usize, to: usize) -> Result<(), Error> {
+        if let Some(id) = [from, to].iter().find(|id| **id >= self.machines.len()) {
+            return Err(Error::NoSuchMachine(*id));
+        }
+        self.links.push((from, to));
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    pub fn machine(&self, id: usize) -> &Program {
+        &self.machines[id]
+    }
+
+    pub fn machine_mut(&mut self, id: usize) -> &mut Program {
+        &mut self.machines[id]
+    }
+
+    pub fn push_input(&mut self, id: usize, value: Value) {
+        self.machines[id].push_input(value)
+    }
+
+    pub fn halted(&self, id: usize) -> bool {
+        self.halted[id]
+    }
+
+    // The most recent value the machine passed on to the machines it feeds.
+    pub fn last_output(&self, id: usize) -> Option<Value> {
+        self.last_output[id]
+    }
+
+    // Give one machine a turn. Returns whether it did anything.
+    fn turn(&mut self, id: usize) -> Result<bool, Error> {
+        let linked = self.links.iter().any(|(from, _)| *from == id);
+        let program = &mut self.machines[id];
+        if linked && program.output.is_some() {
+            return Err(Error::OutputSink { machine: id });
+        }
+        let start = program.steps();
+        let mut status = Status::Running;
+        while status == Status::Running && program.steps() - start < self.quantum {
+            status = program.step().map_err(|error| Error::Machine { machine: id, error })?;
+        }
+        if status == Status::Halted {
+            self.halted[id] = true;
+        }
+        let progress = program.steps() != start;
+
+        let targets: Vec<usize> = self.links
+            .iter()
+            .filter(|(from, _)| *from == id)
+            .map(|(_, to)| *to)
+            .collect();
+        if !targets.is_empty() {
+            let outputs = self.machines[id].take_output();
+            if let Some(last) = outputs.last() {
+                self.last_output[id] = Some(*last);
+            }
+            for to in targets {
+                for value in &outputs {
+                    self.machines[to].push_input(*value);
+                }
+            }
+        }
+
+        Ok(progress)
+    }
+
+    // Run every machine until all of them halt, or until none of them can
+    // make progress.
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            let mut progress = false;
+            for id in 0..self.machines.len() {
+                if !self.halted[id] {
+                    progress |= self.turn(id)?;
+                }
+            }
+
+            if self.halted.iter().all(|h| *h) {
+                return Ok(());
+            }
+            if !progress {
+                let blocked = (0..self.machines.len())
+                    .filter(|id| !self.halted[*id])
+                    .map(|id| (id, self.machines[id].pc()))
+                    .collect();
+                return Err(Error::Deadlock { blocked });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod sched {
+        use super::super::*;
+
+        fn amplifiers(code: &[Value], phases: &[Value], feedback: bool) -> Scheduler {
+            let programs = phases
+                .iter()
+                .map(|phase| {
+                    let mut p = Program::load(code.to_vec());
+                    p.push_input(*phase);
+                    p
+                })
+                .collect();
+            let mut s = match feedback {
+                true => Scheduler::ring(programs),
+                false => Scheduler::pipeline(programs),
+            };
+            s.push_input(0, 0);
+            s
+        }
+
+        #[test]
+        fn pipeline() {
+            let code = [3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+            let mut s = amplifiers(&code, &[4, 3, 2, 1, 0], false);
+            assert_eq!(Ok(()), s.run());
+            assert_eq!(vec![43210], s.machine_mut(4).take_output());
+            assert_eq!(Some(4), s.last_output(0));
+        }
+
+        #[test]
+        fn ring() {
+            let code = [
+                3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26,
+                27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+            ];
+            let mut s = amplifiers(&code, &[9, 8, 7, 6, 5], true);
+            assert_eq!(Ok(()), s.run());
+            assert_eq!(Some(139629729), s.last_output(4));
+            assert!((0..5).all(|id| s.halted(id)));
+        }
+
+        #[test]
+        fn graph_fan_out() {
+            let mut s = Scheduler::new();
+            // doubles its input
+            let source = s.add(Program::load(vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]));
+            // each adds one
+            let a = s.add(Program::load(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]));
+            let b = s.add(Program::load(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]));
+            s.connect(source, a).unwrap();
+            s.connect(source, b).unwrap();
+            s.push_input(source, 21);
+            assert_eq!(Ok(()), s.run());
+            assert_eq!(vec![43], s.machine_mut(a).take_output());
+            assert_eq!(vec![43], s.machine_mut(b).take_output());
+        }
+
+        #[test]
+        fn deadlock() {
+            let echo = vec![3, 7, 4, 7, 1105, 1, 0, 0];
+            let mut s = Scheduler::ring(vec![Program::load(echo.clone()), Program::load(vec![99]), Program::load(echo)]);
+            s.push_input(0, 1);
+            assert_eq!(Err(Error::Deadlock { blocked: vec![(0, 0), (2, 0)] }), s.run());
+        }
+
+        #[test]
+        fn bad_links() {
+            let mut s = Scheduler::new();
+            let a = s.add(Program::load(vec![104, 1, 99]));
+            assert_eq!(Err(Error::NoSuchMachine(1)), s.connect(a, 1));
+            assert_eq!(Err(Error::NoSuchMachine(5)), s.connect(5, a));
+
+            // output going to a sink couldn't be routed
+            let b = s.add(Program::load(vec![3, 0, 99]));
+            s.connect(a, b).unwrap();
+            s.machine_mut(a).set_output(super::super::super::io::Queue::new());
+            assert_eq!(Err(Error::OutputSink { machine: a }), s.run());
+            // unlinked machines can use one
+            s.machine_mut(b).set_output(super::super::super::io::Queue::new());
+            assert_eq!("machine 0 has links and an output sink", s.run().unwrap_err().to_string());
+        }
+
+        #[test]
+        fn machine_error() {
+            let mut s = Scheduler::pipeline(vec![Program::load(vec![99]), Program::load(vec![42])]);
+            assert_eq!(
+                Err(Error::Machine { machine: 1, error: super::super::super::Error::UnknownOpcode { pc: 0, instruction: 42 } }),
+                s.run(),
+            );
+        }
+
+        #[test]
+        fn busy_machine_does_not_starve() {
+            // machine 0 loops forever, machine 1 still gets to halt
+            let mut s = Scheduler::new();
+            s.add(Program::load(vec![1105, 1, 0]));
+            s.add(Program::load(vec![104, 1, 99]));
+            s.set_quantum(10);
+            for _ in 0..2 {
+                let _ = s.turn(0);
+                let _ = s.turn(1);
+            }
+            assert!(s.halted(1));
+            assert!(!s.halted(0));
+        }
+    }
+}
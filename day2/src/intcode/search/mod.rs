@@ -0,0 +1,280 @@
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use super::budget::Budget;
+use super::snapshot::Snapshot;
+use super::{Program, Status, Value};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Report {
+    // The parameter values of each match, in the order the parameters were
+    // added. `first` returns at most one.
+    pub matches: Vec<Vec<Value>>,
+    // How many candidates were actually run.
+    pub evaluations: u64,
+}
+
+fn range_size(range: &RangeInclusive<Value>) -> u64 {
+    match range.end().checked_sub(*range.start()) {
+        Some(span) if span >= 0 => (span as u64).saturating_add(1),
+        Some(_) => 0,
+        // wider than a Value can hold
+        None => u64::MAX,
+    }
+}
+
+// Looks for values of some memory cells (like day 2's noun and verb) that
+// make a program leave `target` in its output cell, trying candidates on
+// several threads at once.
+#[derive(Debug, Clone)]
+pub struct Search {
+    code: Vec<Value>,
+    target: Value,
+    params: Vec<(usize, RangeInclusive<Value>)>,
+    output: usize,
+    threads: usize,
+    budget: Budget,
+}
+
+impl Search {
+    pub fn new(code: Vec<Value>, target: Value) -> Search {
+        Search {
+            code,
+            target,
+            params: Vec::new(),
+            output: 0,
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            budget: Budget::default(),
+        }
+    }
+
+    // Try every value in `range` for the cell at `address`.
+    pub fn param(mut self, address: usize, range: RangeInclusive<Value>) -> Search {
+        self.params.push((address, range));
+        self
+    }
+
+    // Read the result from `address` instead of 0.
+    pub fn output(mut self, address: usize) -> Search {
+        self.output = address;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Search {
+        self.threads = threads.max(1);
+        self
+    }
+
+    // Run each candidate on `budget`, so programs that never halt for some
+    // inputs count as misses rather than hanging the search.
+    pub fn budget(mut self, budget: Budget) -> Search {
+        self.budget = budget;
+        self
+    }
+
+    fn candidates(&self) -> u64 {
+        self.params
+            .iter()
+            .fold(1, |total, (_, range)| total.saturating_mul(range_size(range)))
+    }
+
+    // The parameter values for candidate `index`, the first parameter
+    // changing slowest.
+    fn candidate(&self, mut index: u64) -> Vec<Value> {
+        let mut values = vec![0; self.params.len()];
+        for (idx, (_, range)) in self.params.iter().enumerate().rev() {
+            let size = range_size(range);
+            // in a range wider than Value::MAX the offset doesn't fit in a
+            // Value, but wrapping still lands inside the range
+            values[idx] = range.start().wrapping_add((index % size) as Value);
+            index /= size;
+        }
+        values
+    }
+
+    fn matches(&self, program: &mut Program, start: &Snapshot, values: &[Value]) -> bool {
+        program.restore(start);
+        for ((address, _), value) in self.params.iter().zip(values) {
+            program.set_memory_at(*address, *value);
+        }
+        // candidates that crash or stop to wait for input just aren't matches
+        match program.run() {
+            Ok(Status::Halted) => program.memory().get(self.output) == self.target,
+            _ => false,
+        }
+    }
+
+    fn search(&self, stop_at_first: bool) -> Report {
+        let total = self.candidates();
+        let threads = (self.threads as u64).min(total).max(1);
+        let evaluations = AtomicU64::new(0);
+        // lowest matching index seen so far, so `first` is deterministic no
+        // matter which thread gets there first
+        let best = AtomicU64::new(u64::MAX);
+
+        let mut found: Vec<u64> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|worker| {
+                    let evaluations = &evaluations;
+                    let best = &best;
+                    scope.spawn(move || {
+                        let mut program = Program::load(self.code.clone());
+                        program.set_budget(self.budget);
+                        let start = program.snapshot();
+                        let mut found = Vec::new();
+                        let mut index = worker;
+                        while index < total {
+                            if stop_at_first && index > best.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            evaluations.fetch_add(1, Ordering::Relaxed);
+                            if self.matches(&mut program, &start, &self.candidate(index)) {
+                                found.push(index);
+                                if stop_at_first {
+                                    best.fetch_min(index, Ordering::Relaxed);
+                                    break;
+                                }
+                            }
+                            index += threads;
+                        }
+                        found
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+
+        found.sort_unstable();
+        if stop_at_first {
+            found.truncate(1);
+        }
+        Report {
+            matches: found.into_iter().map(|index| self.candidate(index)).collect(),
+            evaluations: evaluations.into_inner(),
+        }
+    }
+
+    // Stop as soon as a match is found. If several threads find one, the
+    // match that comes first in candidate order wins.
+    pub fn first(&self) -> Report {
+        self.search(true)
+    }
+
+    pub fn all(&self) -> Report {
+        self.search(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod search {
+        use super::super::*;
+
+        fn day2() -> Vec<Value> {
+            Program::load_from_file("data/input.txt").unwrap().memory().to_vec()
+        }
+
+        #[test]
+        fn candidates() {
+            let s = Search::new(vec![99], 0).param(1, 0..=2).param(2, 5..=6);
+            assert_eq!(6, s.candidates());
+            assert_eq!(vec![0, 5], s.candidate(0));
+            assert_eq!(vec![0, 6], s.candidate(1));
+            assert_eq!(vec![2, 6], s.candidate(5));
+            let (low, high) = (3, 2);
+            assert_eq!(0, Search::new(vec![99], 0).param(1, low..=high).candidates());
+
+            let s = Search::new(vec![99], 0).param(1, Value::MIN..=Value::MAX);
+            assert_eq!(u64::MAX, s.candidates());
+            assert_eq!(vec![Value::MIN], s.candidate(0));
+            assert_eq!(vec![0], s.candidate(1 << 63));
+            assert_eq!(vec![Value::MAX - 1], s.candidate(u64::MAX - 1));
+        }
+
+        #[test]
+        fn first_day2() {
+            for threads in [1, 4] {
+                let report = Search::new(day2(), 19690720)
+                    .param(1, 0..=99)
+                    .param(2, 0..=99)
+                    .threads(threads)
+                    .first();
+                assert_eq!(vec![vec![71, 95]], report.matches);
+                assert!(report.evaluations >= 7196, "{}", report.evaluations);
+            }
+        }
+
+        #[test]
+        fn first_single_thread_stops() {
+            let report = Search::new(day2(), 19690720)
+                .param(1, 0..=99)
+                .param(2, 0..=99)
+                .threads(1)
+                .first();
+            assert_eq!(7196, report.evaluations);
+        }
+
+        #[test]
+        fn all_three_cells() {
+            // memory[0] = a + b + c
+            let code = vec![1, 9, 10, 0, 1, 0, 11, 0, 99, 0, 0, 0];
+            let report = Search::new(code, 7)
+                .param(9, 0..=3)
+                .param(10, 0..=3)
+                .param(11, 0..=3)
+                .threads(3)
+                .all();
+            let mut expected = Vec::new();
+            for a in 0..=3 {
+                for b in 0..=3 {
+                    for c in 0..=3 {
+                        if a + b + c == 7 {
+                            expected.push(vec![a, b, c]);
+                        }
+                    }
+                }
+            }
+            assert_eq!(expected, report.matches);
+            assert_eq!(64, report.evaluations);
+        }
+
+        #[test]
+        fn output_cell_and_no_match() {
+            // memory[5] = memory[5] * memory[6]
+            let code = vec![2, 5, 6, 5, 99, 0, 0];
+            let report = Search::new(code.clone(), 12).param(5, 1..=4).param(6, 1..=4).output(5).all();
+            assert_eq!(vec![vec![3, 4], vec![4, 3]], report.matches);
+
+            let report = Search::new(code, 100).param(5, 1..=4).param(6, 1..=4).output(5).first();
+            assert_eq!(Vec::<Vec<Value>>::new(), report.matches);
+            assert_eq!(16, report.evaluations);
+        }
+
+        #[test]
+        fn budget() {
+            // memory[0] = 2 * memory[9], except 0 loops forever
+            let code = vec![1006, 9, 0, 1, 9, 9, 0, 99, 0, 0];
+            for budget in [
+                Budget { steps: Some(100), ..Default::default() },
+                Budget { detect_loops: true, ..Default::default() },
+            ] {
+                let report = Search::new(code.clone(), 4).param(9, 0..=3).budget(budget).threads(2).all();
+                assert_eq!(vec![vec![2]], report.matches);
+            }
+        }
+
+        #[test]
+        fn waiting_for_input_is_no_match() {
+            // memory[0] = memory[20] + 1, then reads input if memory[20] is 2
+            let mut code = vec![1001, 20, 1, 0, 1008, 20, 2, 21, 1005, 21, 12, 99, 3, 22, 99];
+            code.resize(23, 0);
+            let report = Search::new(code.clone(), 3).param(20, 0..=3).all();
+            assert_eq!(Vec::<Vec<Value>>::new(), report.matches);
+            assert_eq!(4, report.evaluations);
+
+            let report = Search::new(code, 4).param(20, 0..=3).threads(1).all();
+            assert_eq!(vec![vec![3]], report.matches);
+        }
+    }
+}
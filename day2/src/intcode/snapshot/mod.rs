@@ -0,0 +1,233 @@
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use super::memory::Memory;
+use super::{varint, Value};
+
+const MAGIC: &[u8; 4] = b"ICSN";
+// Version 1 didn't record whether the machine had halted.
+const VERSION: u64 = 2;
+
+// The complete state of a machine at some point of its run: memory,
+// registers and any input or output still sitting in its buffers. Memory is
+// shared with the machine until either side writes to it, so taking a
+// snapshot is cheap.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub memory: Memory,
+    pub pc: usize,
+    pub relative_base: Value,
+    pub steps: u64,
+    // Whether HLT has already run.
+    pub halted: bool,
+    pub inputs: Vec<Value>,
+    pub outputs: Vec<Value>,
+}
+
+fn write_values<W: Write>(out: &mut W, values: &[Value]) -> io::Result<()> {
+    varint::write_u64(out, values.len() as u64)?;
+    for value in values {
+        varint::write_value(out, *value)?;
+    }
+    Ok(())
+}
+
+fn read_values<R: Read>(input: &mut R) -> io::Result<Vec<Value>> {
+    let count = varint::read_u64(input)?;
+    // don't trust the count for the allocation, a corrupt file would
+    // otherwise ask for an enormous vector
+    let mut values = Vec::new();
+    for _ in 0..count {
+        values.push(varint::read_value(input)?);
+    }
+    Ok(values)
+}
+
+impl Snapshot {
+    // Only non-zero memory cells are stored, as (address delta, value) pairs.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        varint::write_u64(out, VERSION)?;
+        varint::write_u64(out, self.pc as u64)?;
+        varint::write_value(out, self.relative_base)?;
+        varint::write_u64(out, self.steps)?;
+        varint::write_u64(out, self.halted as u64)?;
+        write_values(out, &self.inputs)?;
+        write_values(out, &self.outputs)?;
+
+        varint::write_u64(out, self.memory.len() as u64)?;
+        let cells = self.memory.nonzero();
+        varint::write_u64(out, cells.len() as u64)?;
+        let mut last = 0;
+        for (address, value) in cells {
+            varint::write_u64(out, (address - last) as u64)?;
+            varint::write_value(out, value)?;
+            last = address;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Snapshot> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        let version = match varint::read_u64(input)? {
+            version @ 1..=VERSION => version,
+            _ => return Err(invalid("unsupported snapshot version")),
+        };
+
+        let pc = varint::read_u64(input)? as usize;
+        let relative_base = varint::read_value(input)?;
+        let steps = varint::read_u64(input)?;
+        let halted = match version {
+            1 => false,
+            _ => match varint::read_u64(input)? {
+                0 => false,
+                1 => true,
+                _ => return Err(invalid("bad halted flag")),
+            },
+        };
+        let inputs = read_values(input)?;
+        let outputs = read_values(input)?;
+
+        let len = varint::read_u64(input)? as usize;
+        let mut memory = Memory::new();
+        let mut address: usize = 0;
+        for _ in 0..varint::read_u64(input)? {
+            address = match address.checked_add(varint::read_u64(input)? as usize) {
+                Some(address) => address,
+                None => return Err(invalid("memory address out of range")),
+            };
+            memory.set(address, varint::read_value(input)?);
+        }
+        memory.extend_to(len);
+
+        Ok(Snapshot { memory, pc, relative_base, steps, halted, inputs, outputs })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        self.write_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn load(path: &str) -> io::Result<Snapshot> {
+        Snapshot::read_from(&mut BufReader::new(fs::File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod snapshot {
+        use super::super::*;
+        use super::super::super::{Program, Status};
+
+        // Reads a value, outputs it doubled, forever.
+        fn doubler() -> Program {
+            Program::load(vec![3, 9, 1002, 9, 2, 9, 4, 9, 1105, 1, 0])
+        }
+
+        #[test]
+        fn restore() {
+            let mut p = doubler();
+            p.push_input(1);
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+            p.push_input(5);
+            let snapshot = p.snapshot();
+            assert_eq!(vec![5], snapshot.inputs);
+            assert_eq!(vec![2], snapshot.outputs);
+
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+            assert_eq!(vec![2, 10], p.take_output());
+
+            p.restore(&snapshot);
+            assert_eq!(snapshot.steps, p.steps());
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+            assert_eq!(vec![2, 10], p.take_output());
+        }
+
+        #[test]
+        fn fork() {
+            let mut p = doubler();
+            p.push_input(3);
+            assert_eq!(Ok(Status::WaitingForInput), p.run());
+
+            let mut f = p.fork();
+            f.push_input(4);
+            assert_eq!(Ok(Status::WaitingForInput), f.run());
+            assert_eq!(vec![6, 8], f.take_output());
+            assert_eq!(Some(8), f.memory_at(9));
+
+            assert_eq!(Some(6), p.memory_at(9));
+            assert_eq!(vec![6], p.take_output());
+        }
+
+        #[test]
+        fn serialize() {
+            let mut p = doubler();
+            p.set_memory_at(1 << 30, -12);
+            p.push_input(21);
+            p.push_input(-3);
+            assert_eq!(Ok(Status::Running), p.step());
+            let snapshot = p.snapshot();
+
+            let mut data = Vec::new();
+            snapshot.write_to(&mut data).unwrap();
+            assert_eq!(b"ICSN", &data[..4]);
+            let loaded = Snapshot::read_from(&mut data.as_slice()).unwrap();
+            assert_eq!(snapshot.memory.len(), loaded.memory.len());
+            assert_eq!(snapshot.memory.nonzero(), loaded.memory.nonzero());
+            assert_eq!(2, loaded.pc);
+            assert_eq!(1, loaded.steps);
+            assert_eq!(vec![-3], loaded.inputs);
+
+            let mut q = doubler();
+            q.restore(&loaded);
+            assert_eq!(Ok(Status::WaitingForInput), q.run());
+            assert_eq!(vec![42, -6], q.take_output());
+        }
+
+        #[test]
+        fn halted() {
+            let mut p = Program::load(vec![1101, 1, 2, 5, 99, 0]);
+            assert_eq!(Ok(Status::Halted), p.run());
+            let snapshot = p.snapshot();
+            assert!(snapshot.halted);
+
+            let mut data = Vec::new();
+            snapshot.write_to(&mut data).unwrap();
+            let loaded = Snapshot::read_from(&mut data.as_slice()).unwrap();
+            assert!(loaded.halted);
+
+            // neither a restored machine nor a fork runs the HLT again
+            let mut q = Program::load(vec![1101, 1, 2, 5, 99, 0]);
+            q.restore(&loaded);
+            assert_eq!(Ok(Status::Halted), q.step());
+            assert_eq!(2, q.steps());
+            let mut f = p.fork();
+            assert_eq!(Ok(Status::Halted), f.run());
+            assert_eq!(2, f.steps());
+        }
+
+        #[test]
+        fn read_errors() {
+            assert!(Snapshot::read_from(&mut &b"ICSX"[..]).is_err());
+            assert!(Snapshot::read_from(&mut &b"ICSN\x03"[..]).is_err());
+            assert!(Snapshot::read_from(&mut &b"ICSN\x01\x00"[..]).is_err());
+
+            // a version 1 snapshot, with a delta straight to the last address
+            let mut data = b"ICSN".to_vec();
+            for n in [1, 0, 0, 0, 0, 0, 0, 1, u64::MAX] {
+                varint::write_u64(&mut data, n).unwrap();
+            }
+            varint::write_value(&mut data, 7).unwrap();
+            let loaded = Snapshot::read_from(&mut data.as_slice()).unwrap();
+            assert_eq!(vec![(usize::MAX, 7)], loaded.memory.nonzero());
+            assert!(!loaded.halted);
+        }
+    }
+}
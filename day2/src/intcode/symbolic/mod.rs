@@ -0,0 +1,559 @@
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::{Instruction, Mode, Program, Value, OP_ADD, OP_ARB, OP_EQ, OP_HLT, OP_JF, OP_JT, OP_LT, OP_MUL};
+
+// Give up on programs that run longer than this, they're probably looping on
+// something that isn't going to be decidable anyway.
+const STEP_LIMIT: u64 = 1_000_000;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    // The opcode itself depends on a variable.
+    SymbolicInstruction { pc: usize },
+    // An address, jump target or relative base depends on a variable.
+    SymbolicAddress { pc: usize },
+    // A comparison or jump condition can't be decided without knowing the
+    // variables.
+    Undecidable { pc: usize },
+    // Input and output aren't modelled.
+    Unsupported { pc: usize, instruction: Value },
+    Machine(super::Error),
+    Overflow { pc: usize },
+    StepLimit,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SymbolicInstruction { pc } => write!(f, "instruction at pc {} depends on a variable", pc),
+            Error::SymbolicAddress { pc } => write!(f, "address used at pc {} depends on a variable", pc),
+            Error::Undecidable { pc } => write!(f, "condition at pc {} depends on a variable", pc),
+            Error::Unsupported { pc, instruction } =>
+                write!(f, "instruction {} at pc {} isn't supported symbolically", instruction, pc),
+            Error::Machine(e) => write!(f, "{}", e),
+            Error::Overflow { pc } => write!(f, "overflow at pc {}", pc),
+            Error::StepLimit => write!(f, "gave up after {} steps", STEP_LIMIT),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+// A polynomial over memory cell variables with integer coefficients. Each
+// monomial is the sorted list of variables multiplied together, the empty
+// list being the constant term.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Expr {
+    terms: BTreeMap<Vec<usize>, Value>,
+}
+
+impl Expr {
+    pub fn constant(value: Value) -> Expr {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(Vec::new(), value);
+        }
+        Expr { terms }
+    }
+
+    // The variable standing for the initial value of memory cell `address`.
+    pub fn var(address: usize) -> Expr {
+        let mut terms = BTreeMap::new();
+        terms.insert(vec![address], 1);
+        Expr { terms }
+    }
+
+    pub fn as_constant(&self) -> Option<Value> {
+        match self.terms.len() {
+            0 => Some(0),
+            1 => self.terms.get(&Vec::new()).cloned(),
+            _ => None,
+        }
+    }
+
+    // The coefficient of a monomial, e.g. `&[]` for the constant term or
+    // `&[1]` for the variable at address 1.
+    pub fn coefficient(&self, monomial: &[usize]) -> Value {
+        self.terms.get(monomial).cloned().unwrap_or(0)
+    }
+
+    pub fn vars(&self) -> Vec<usize> {
+        let mut vars: Vec<usize> = self.terms.keys().flatten().cloned().collect();
+        vars.sort_unstable();
+        vars.dedup();
+        vars
+    }
+
+    pub fn is_linear(&self) -> bool {
+        self.terms.keys().all(|monomial| monomial.len() <= 1)
+    }
+
+    fn add_term(&mut self, monomial: Vec<usize>, coefficient: Value) -> Option<()> {
+        let entry = self.terms.entry(monomial).or_insert(0);
+        *entry = entry.checked_add(coefficient)?;
+        if *entry == 0 {
+            self.terms.retain(|_, c| *c != 0);
+        }
+        Some(())
+    }
+
+    pub fn checked_add(&self, other: &Expr) -> Option<Expr> {
+        let mut sum = self.clone();
+        for (monomial, coefficient) in &other.terms {
+            sum.add_term(monomial.clone(), *coefficient)?;
+        }
+        Some(sum)
+    }
+
+    pub fn checked_sub(&self, other: &Expr) -> Option<Expr> {
+        let mut difference = self.clone();
+        for (monomial, coefficient) in &other.terms {
+            difference.add_term(monomial.clone(), coefficient.checked_neg()?)?;
+        }
+        Some(difference)
+    }
+
+    pub fn checked_mul(&self, other: &Expr) -> Option<Expr> {
+        let mut product = Expr::default();
+        for (a, ca) in &self.terms {
+            for (b, cb) in &other.terms {
+                let mut monomial = a.clone();
+                monomial.extend(b);
+                monomial.sort_unstable();
+                product.add_term(monomial, ca.checked_mul(*cb)?)?;
+            }
+        }
+        Some(product)
+    }
+
+    // Replace variable `address` with a value.
+    pub fn substitute(&self, address: usize, value: Value) -> Option<Expr> {
+        let mut result = Expr::default();
+        for (monomial, coefficient) in &self.terms {
+            let mut coefficient = *coefficient;
+            let mut rest = Vec::new();
+            for var in monomial {
+                match *var == address {
+                    true => coefficient = coefficient.checked_mul(value)?,
+                    false => rest.push(*var),
+                }
+            }
+            result.add_term(rest, coefficient)?;
+        }
+        Some(result)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+        // highest degree first, constant last
+        let mut terms: Vec<_> = self.terms.iter().collect();
+        terms.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+        for (idx, (monomial, coefficient)) in terms.into_iter().enumerate() {
+            let sign = match (idx, *coefficient < 0) {
+                (0, true) => "-",
+                (0, false) => "",
+                (_, true) => " - ",
+                (_, false) => " + ",
+            };
+            let magnitude = (*coefficient as i128).abs();
+            let vars = monomial.iter().map(|v| format!("m{}", v)).collect::<Vec<_>>().join("*");
+            match (magnitude, vars.is_empty()) {
+                (m, true) => write!(f, "{}{}", sign, m)?,
+                (1, false) => write!(f, "{}{}", sign, vars)?,
+                (m, false) => write!(f, "{}{}*{}", sign, m, vars)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Cells hold None once they've been written with a value read through an
+// address that depends on a variable. That's fine as long as nothing looks
+// at them afterwards, which is how day 2 uses its noun and verb. Memory is
+// sparse like the interpreter's, with cells that were never written reading
+// as 0.
+struct Machine {
+    memory: BTreeMap<usize, Option<Expr>>,
+    pc: usize,
+    relative_base: Value,
+    // The program's memory budget, if it has one.
+    limit: Option<usize>,
+}
+
+impl Machine {
+    fn get(&self, address: usize) -> Option<Expr> {
+        match self.memory.get(&address) {
+            Some(cell) => cell.clone(),
+            None => Some(Expr::default()),
+        }
+    }
+
+    fn concrete(&self, address: usize) -> Result<Value, Error> {
+        self.get(address)
+            .and_then(|expr| expr.as_constant())
+            .ok_or(Error::SymbolicAddress { pc: self.pc })
+    }
+
+    fn addr(&self, inst: &Instruction, param: usize) -> Result<usize, Error> {
+        let operand = self.concrete(self.pc + param)?;
+        let address = match inst.mode(param) {
+            Mode::Position => operand,
+            Mode::Relative => self.relative_base.checked_add(operand).ok_or(Error::Overflow { pc: self.pc })?,
+            Mode::Immediate => return Err(Error::Machine(super::Error::BadMode { pc: self.pc, instruction: inst.raw, param })),
+        };
+        match address {
+            a if a < 0 => Err(Error::Machine(super::Error::ReadOutOfBounds { pc: self.pc, instruction: inst.raw, address: a })),
+            a => Ok(a as usize),
+        }
+    }
+
+    fn read(&self, inst: &Instruction, param: usize) -> Result<Option<Expr>, Error> {
+        match inst.mode(param) {
+            Mode::Immediate => Ok(self.get(self.pc + param)),
+            _ => match self.addr(inst, param) {
+                Ok(address) => Ok(self.get(address)),
+                Err(Error::SymbolicAddress { .. }) => Ok(None),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn read_concrete(&self, inst: &Instruction, param: usize, err: Error) -> Result<Value, Error> {
+        self.read(inst, param)?.and_then(|expr| expr.as_constant()).ok_or(err)
+    }
+
+    fn write(&mut self, inst: &Instruction, param: usize, value: Option<Expr>) -> Result<(), Error> {
+        let address = self.addr(inst, param)?;
+        if matches!(self.limit, Some(limit) if address >= limit) {
+            return Err(Error::Machine(super::Error::MemoryLimit { pc: self.pc, instruction: inst.raw, address }));
+        }
+        self.memory.insert(address, value);
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<bool, Error> {
+        let raw = self.get(self.pc)
+            .and_then(|expr| expr.as_constant())
+            .ok_or(Error::SymbolicInstruction { pc: self.pc })?;
+        let inst = Instruction::decode(self.pc, raw).map_err(Error::Machine)?;
+        let overflow = Error::Overflow { pc: self.pc };
+        match inst.op {
+            OP_ADD | OP_MUL => {
+                let result = match (self.read(&inst, 1)?, self.read(&inst, 2)?) {
+                    (Some(a), Some(b)) => Some(match inst.op {
+                        OP_ADD => a.checked_add(&b),
+                        _ => a.checked_mul(&b),
+                    }.ok_or(overflow)?),
+                    _ => None,
+                };
+                self.write(&inst, 3, result)?;
+                self.pc += 4;
+            }
+            OP_LT | OP_EQ => {
+                let undecidable = Error::Undecidable { pc: self.pc };
+                let (a, b) = match (self.read(&inst, 1)?, self.read(&inst, 2)?) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => return Err(undecidable),
+                };
+                // the comparison is decidable when the difference doesn't
+                // depend on any variable
+                let difference = a.checked_sub(&b).ok_or(overflow)?;
+                let result = match (inst.op, difference.as_constant()) {
+                    (OP_LT, Some(d)) => d < 0,
+                    (_, Some(d)) => d == 0,
+                    (_, None) => return Err(undecidable),
+                };
+                self.write(&inst, 3, Some(Expr::constant(result as Value)))?;
+                self.pc += 4;
+            }
+            OP_JT | OP_JF => {
+                let cond = self.read_concrete(&inst, 1, Error::Undecidable { pc: self.pc })?;
+                let target = self.read_concrete(&inst, 2, Error::SymbolicAddress { pc: self.pc })?;
+                match (inst.op == OP_JT) == (cond != 0) {
+                    true if target < 0 => return Err(Error::Machine(super::Error::JumpOutOfBounds {
+                        pc: self.pc,
+                        instruction: inst.raw,
+                        target,
+                    })),
+                    true => self.pc = target as usize,
+                    false => self.pc += 3,
+                }
+            }
+            OP_ARB => {
+                let offset = self.read_concrete(&inst, 1, Error::SymbolicAddress { pc: self.pc })?;
+                self.relative_base = self.relative_base.checked_add(offset).ok_or(overflow)?;
+                self.pc += 2;
+            }
+            OP_HLT => return Ok(true),
+            _ => return Err(Error::Unsupported { pc: self.pc, instruction: inst.raw }),
+        }
+        Ok(false)
+    }
+}
+
+// Run `program` from where it is with the cells in `vars` treated as
+// unknowns and return the expression left in cell `output` when it halts.
+pub fn evaluate(program: &Program, vars: &[usize], output: usize) -> Result<Expr, Error> {
+    let mut machine = Machine {
+        memory: program.memory().nonzero().into_iter().map(|(a, v)| (a, Some(Expr::constant(v)))).collect(),
+        pc: program.pc(),
+        relative_base: program.relative_base(),
+        limit: program.budget().memory,
+    };
+    for var in vars {
+        machine.memory.insert(*var, Some(Expr::var(*var)));
+    }
+
+    for _ in 0..STEP_LIMIT {
+        if machine.step()? {
+            return machine.get(output).ok_or(Error::SymbolicAddress { pc: machine.pc });
+        }
+    }
+    Err(Error::StepLimit)
+}
+
+fn floor_div(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    match (a % b != 0) && ((a < 0) != (b < 0)) {
+        true => q - 1,
+        false => q,
+    }
+}
+
+fn ceil_div(a: i128, b: i128) -> i128 {
+    -floor_div(-a, b)
+}
+
+// Returns (g, x, y) with a*x + b*y = g = gcd(a, b).
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    match b {
+        0 if a < 0 => (-a, -1, 0),
+        0 => (a, 1, 0),
+        _ => {
+            let (g, x, y) = extended_gcd(b, a % b);
+            (g, y, x - (a / b) * y)
+        }
+    }
+}
+
+// The range of k for which start + k * step lies in `range`.
+fn k_bounds(start: i128, step: i128, range: &RangeInclusive<Value>) -> Option<(i128, i128)> {
+    let (low, high) = ((*range.start() as i128).checked_sub(start)?, (*range.end() as i128).checked_sub(start)?);
+    match step > 0 {
+        true => Some((ceil_div(low, step), floor_div(high, step))),
+        false => Some((ceil_div(high, step), floor_div(low, step))),
+    }
+}
+
+// Solve a*x + b*y = rhs with both a and b non-zero, straight from the
+// extended Euclidean algorithm instead of trying values. Solutions whose
+// working doesn't fit in an i128 aren't found.
+fn solve_two(a: i128, b: i128, rhs: i128, x_range: &RangeInclusive<Value>, y_range: &RangeInclusive<Value>) -> Option<(Value, Value)> {
+    let (g, x0, y0) = extended_gcd(a, b);
+    if rhs % g != 0 {
+        return None;
+    }
+    let (x0, y0) = (x0.checked_mul(rhs / g)?, y0.checked_mul(rhs / g)?);
+    let (x_step, y_step) = (b / g, -a / g);
+
+    let (x_low, x_high) = k_bounds(x0, x_step, x_range)?;
+    let (y_low, y_high) = k_bounds(y0, y_step, y_range)?;
+    let k = x_low.max(y_low);
+    if k > x_high.min(y_high) {
+        return None;
+    }
+    let x = x0.checked_add(k.checked_mul(x_step)?)?;
+    let y = y0.checked_add(k.checked_mul(y_step)?)?;
+    Some((x as Value, y as Value))
+}
+
+fn solve_linear(expr: &Expr, target: Value, vars: &[(usize, RangeInclusive<Value>)]) -> Option<Vec<Value>> {
+    let rhs = target as i128 - expr.coefficient(&[]) as i128;
+    let used: Vec<usize> = (0..vars.len()).filter(|idx| expr.coefficient(&[vars[*idx].0]) != 0).collect();
+
+    // variables the expression doesn't use can be anything in their range
+    let mut values: Vec<Value> = vars.iter().map(|(_, range)| *range.start()).collect();
+    match used.as_slice() {
+        [] if rhs == 0 => (),
+        [x] => {
+            let a = expr.coefficient(&[vars[*x].0]) as i128;
+            if rhs % a != 0 || !vars[*x].1.contains(&((rhs / a) as Value)) {
+                return None;
+            }
+            values[*x] = (rhs / a) as Value;
+        }
+        [x, y] => {
+            let a = expr.coefficient(&[vars[*x].0]) as i128;
+            let b = expr.coefficient(&[vars[*y].0]) as i128;
+            let (vx, vy) = solve_two(a, b, rhs, &vars[*x].1, &vars[*y].1)?;
+            values[*x] = vx;
+            values[*y] = vy;
+        }
+        _ => return None,
+    }
+    Some(values)
+}
+
+// Find values for `vars`, each within its range, that make `expr` equal
+// `target`. Linear expressions in up to two variables are solved directly;
+// anything else has variables substituted one at a time until it is.
+pub fn solve(expr: &Expr, target: Value, vars: &[(usize, RangeInclusive<Value>)]) -> Option<Vec<Value>> {
+    if vars.iter().any(|(_, range)| range.is_empty()) {
+        return None;
+    }
+
+    let used = expr.vars();
+    if used.iter().any(|v| !vars.iter().any(|(address, _)| address == v)) {
+        // depends on a variable we weren't told the range of
+        return None;
+    }
+    if expr.is_linear() && used.len() <= 2 {
+        return solve_linear(expr, target, vars);
+    }
+
+    let (address, range) = vars.iter().find(|(address, _)| used.contains(address))?;
+    let position = vars.iter().position(|(a, _)| a == address)?;
+    for value in range.clone() {
+        let reduced = match expr.substitute(*address, value) {
+            Some(reduced) => reduced,
+            None => continue,
+        };
+        let mut rest = vars.to_vec();
+        rest[position] = (*address, value..=value);
+        if let Some(values) = solve(&reduced, target, &rest) {
+            return Some(values);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    mod symbolic {
+        use super::super::*;
+        use super::super::super::Program;
+
+        fn program(code: &[Value]) -> Program {
+            Program::load(code.to_vec())
+        }
+
+        #[test]
+        fn expr_arithmetic() {
+            let x = Expr::var(1);
+            let y = Expr::var(2);
+            let sum = x.checked_add(&Expr::constant(3)).unwrap();
+            let product = sum.checked_mul(&y).unwrap();
+            assert_eq!("m1*m2 + 3*m2", product.to_string());
+            assert!(!product.is_linear());
+            assert_eq!(vec![1, 2], product.vars());
+            assert_eq!(Some(5), product.substitute(1, 2).unwrap().substitute(2, 1).unwrap().as_constant());
+            assert_eq!(Expr::constant(0), x.checked_sub(&x).unwrap());
+            assert_eq!("-m1 + 4", Expr::constant(4).checked_sub(&x).unwrap().to_string());
+            assert_eq!(None, Expr::constant(Value::MAX).checked_add(&Expr::constant(1)));
+        }
+
+        #[test]
+        fn day2_is_linear() {
+            let program = Program::load_from_file("data/input.txt").unwrap();
+            let expr = evaluate(&program, &[1, 2], 0).unwrap();
+            assert!(expr.is_linear());
+            assert_eq!(1, expr.coefficient(&[2]));
+
+            let mut p = Program::load_from_file("data/input.txt").unwrap();
+            for (noun, verb) in [(0, 0), (12, 2), (71, 95)] {
+                let value = expr.substitute(1, noun).unwrap().substitute(2, verb).unwrap().as_constant();
+                assert_eq!(Some(p.call(noun, verb).unwrap()), value);
+            }
+
+            let solution = solve(&expr, 19690720, &[(1, 0..=99), (2, 0..=99)]);
+            assert_eq!(Some(vec![71, 95]), solution);
+            assert_eq!(None, solve(&expr, 1, &[(1, 0..=99), (2, 0..=99)]));
+        }
+
+        #[test]
+        fn decidable_jumps() {
+            // memory[0] = m12 + m12, unless 5 < 3 which it isn't
+            let code = [1107, 5, 3, 13, 1005, 13, 11, 1, 12, 12, 0, 99, 0, 0];
+            assert_eq!(Ok(Expr::var(12).checked_mul(&Expr::constant(2)).unwrap()), evaluate(&program(&code), &[12], 0));
+            // comparing x with x + 0 is decidable too
+            let code = [8, 9, 9, 0, 99, 0, 0, 0, 0, 0];
+            assert_eq!(Ok(Expr::constant(1)), evaluate(&program(&code), &[9], 0));
+        }
+
+        #[test]
+        fn undecidable() {
+            let code = [1005, 9, 4, 99, 99, 0, 0, 0, 0, 0];
+            assert_eq!(Err(Error::Undecidable { pc: 0 }), evaluate(&program(&code), &[9], 0));
+            let code = [7, 9, 10, 0, 99, 0, 0, 0, 0, 0, 0];
+            assert_eq!(Err(Error::Undecidable { pc: 0 }), evaluate(&program(&code), &[9, 10], 0));
+            let code = [1, 9, 9, 0, 99, 0, 0, 0, 0, 0];
+            assert_eq!(Err(Error::SymbolicAddress { pc: 4 }), evaluate(&program(&code), &[1], 0));
+            // reading through a variable address is fine until the value is used
+            let code = [1, 9, 9, 10, 1, 11, 11, 0, 99, 0, 0, 21];
+            assert_eq!(Ok(Expr::constant(42)), evaluate(&program(&code), &[1], 0));
+            let code = [1, 9, 9, 10, 1005, 10, 0, 99, 0, 0, 0];
+            assert_eq!(Err(Error::Undecidable { pc: 4 }), evaluate(&program(&code), &[1], 0));
+            assert_eq!(Err(Error::SymbolicInstruction { pc: 0 }), evaluate(&program(&[99]), &[0], 0));
+            assert_eq!(Err(Error::Unsupported { pc: 0, instruction: 3 }), evaluate(&program(&[3, 0, 99]), &[], 0));
+            assert_eq!(Err(Error::StepLimit), evaluate(&program(&[1105, 1, 0]), &[], 0));
+        }
+
+        #[test]
+        fn far_addresses() {
+            let code = [1101, 3, 4, 1 << 40, 1, 1 << 40, 9, 0, 99, 0];
+            assert_eq!(Ok(Expr::constant(7).checked_add(&Expr::var(9)).unwrap()), evaluate(&program(&code), &[9], 0));
+            // the program's memory budget applies
+            let mut p = program(&code);
+            p.set_budget(super::super::super::budget::Budget { memory: Some(1000), ..Default::default() });
+            let limit = super::super::super::Error::MemoryLimit { pc: 0, instruction: 1101, address: 1 << 40 };
+            assert_eq!(Err(Error::Machine(limit)), evaluate(&p, &[9], 0));
+        }
+
+        #[test]
+        fn solve_linear_cases() {
+            // 6x + 10y + 1 = 35 -> x = 4, y = 1 within 0..=5
+            let expr = Expr::var(1).checked_mul(&Expr::constant(6)).unwrap()
+                .checked_add(&Expr::var(2).checked_mul(&Expr::constant(10)).unwrap()).unwrap()
+                .checked_add(&Expr::constant(1)).unwrap();
+            assert_eq!(Some(vec![4, 1]), solve(&expr, 35, &[(1, 0..=5), (2, 0..=5)]));
+            // even values only
+            assert_eq!(None, solve(&expr, 36, &[(1, 0..=5), (2, 0..=5)]));
+            // ranges too small
+            assert_eq!(None, solve(&expr, 35, &[(1, 0..=3), (2, 0..=5)]));
+            // negative coefficients: 6x - 10y = -4 -> x = 1, y = 1
+            let expr = Expr::var(1).checked_mul(&Expr::constant(6)).unwrap()
+                .checked_sub(&Expr::var(2).checked_mul(&Expr::constant(10)).unwrap()).unwrap();
+            assert_eq!(Some(vec![1, 1]), solve(&expr, -4, &[(1, 0..=5), (2, 0..=5)]));
+
+            // a single variable, with an unused one left at its start
+            let expr = Expr::var(1).checked_mul(&Expr::constant(3)).unwrap();
+            assert_eq!(Some(vec![7, 2]), solve(&expr, 21, &[(1, 0..=10), (2, 2..=5)]));
+            assert_eq!(None, solve(&expr, 22, &[(1, 0..=10), (2, 2..=5)]));
+            // constants
+            assert_eq!(Some(vec![0]), solve(&Expr::constant(5), 5, &[(1, 0..=1)]));
+            assert_eq!(None, solve(&Expr::constant(5), 6, &[(1, 0..=1)]));
+            // unknown variable
+            assert_eq!(None, solve(&Expr::var(3), 0, &[(1, 0..=1)]));
+            // working that doesn't fit in an i128 finds nothing rather than
+            // panicking
+            let big = 1i128 << 100;
+            assert_eq!(None, solve_two(big + 1, big - 1, 1 << 60, &(0..=1), &(0..=1)));
+        }
+
+        #[test]
+        fn solve_nonlinear() {
+            // x * y + z = 50
+            let expr = Expr::var(1).checked_mul(&Expr::var(2)).unwrap().checked_add(&Expr::var(3)).unwrap();
+            let solution = solve(&expr, 50, &[(1, 5..=9), (2, 5..=9), (3, 0..=3)]).unwrap();
+            assert_eq!(50, solution[0] * solution[1] + solution[2]);
+            assert_eq!(None, solve(&expr, 500, &[(1, 5..=9), (2, 5..=9), (3, 0..=3)]));
+        }
+    }
+}
@@ -0,0 +1,28 @@
+// Helpers shared by the tests of the engines that stand in for the
+// interpreter.
+
+use super::{Error, Program, Status, Value};
+
+// Run a program on the interpreter and on `engine`, and check they end up in
+// the same place. Returns the engine's program and what it output.
+pub fn differential<F>(code: Vec<Value>, inputs: &[Value], engine: F) -> (Program, Vec<Value>)
+where
+    F: FnOnce(&mut Program) -> Result<Status, Error>,
+{
+    let mut interpreted = Program::load(code.clone());
+    let mut program = Program::load(code);
+    for input in inputs {
+        interpreted.push_input(*input);
+        program.push_input(*input);
+    }
+    assert_eq!(interpreted.run(), engine(&mut program));
+    // compared sparsely since memory may be huge
+    assert_eq!(interpreted.memory().nonzero(), program.memory().nonzero());
+    assert_eq!(interpreted.memory().len(), program.memory().len());
+    assert_eq!(interpreted.pc(), program.pc());
+    assert_eq!(interpreted.relative_base(), program.relative_base());
+    assert_eq!(interpreted.steps(), program.steps());
+    let output = program.take_output();
+    assert_eq!(interpreted.take_output(), output);
+    (program, output)
+}
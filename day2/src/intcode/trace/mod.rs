@@ -0,0 +1,334 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use super::{disasm, op_info, varint, Instruction, Value};
+
+const BINARY_MAGIC: &[u8; 4] = b"ICTR";
+
+// One executed instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Event {
+    // How many instructions ran before this one.
+    pub step: u64,
+    pub pc: usize,
+    pub instruction: Instruction,
+    // The operand words of the instruction.
+    pub operands: Vec<Value>,
+    // Values read through the parameters, in parameter order.
+    pub reads: Vec<Value>,
+    // Address and value written, if any, including values written to a
+    // device rather than memory.
+    pub write: Option<(usize, Value)>,
+}
+
+// Called by `Program` for every instruction it executes.
+pub trait Tracer {
+    fn trace(&mut self, event: &Event);
+}
+
+// Lets the caller keep a handle on a tracer the program owns, e.g. to read a
+// profile once the run is done.
+impl<T: Tracer> Tracer for Rc<RefCell<T>> {
+    fn trace(&mut self, event: &Event) {
+        self.borrow_mut().trace(event)
+    }
+}
+
+// Writes one human readable line per instruction.
+pub struct Log<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Log<W> {
+    pub fn new(out: W) -> Log<W> {
+        Log { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn join(values: &[Value]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+impl<W: Write> Tracer for Log<W> {
+    fn trace(&mut self, event: &Event) {
+        let mut memory = vec![event.instruction.raw];
+        memory.extend(&event.operands);
+        let source = match disasm::decode_at(&memory, 0) {
+            Some(line) => line.source(),
+            None => format!("{}", event.instruction.raw),
+        };
+        let write = match event.write {
+            Some((address, value)) => format!(" [{}]={}", address, value),
+            None => String::new(),
+        };
+        // tracing shouldn't be able to stop the program, so failed writes
+        // are dropped
+        let _ = writeln!(
+            self.out,
+            "{:>8} {:>6}: {:<28} reads({}){}",
+            event.step,
+            event.pc,
+            source,
+            join(&event.reads),
+            write,
+        );
+    }
+}
+
+// Writes a compact binary trace: a magic header followed by varint encoded
+// events. `read_binary` turns a trace back into events.
+pub struct Binary<W: Write> {
+    out: W,
+    started: bool,
+}
+
+impl<W: Write> Binary<W> {
+    pub fn new(out: W) -> Binary<W> {
+        Binary { out, started: false }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        if !self.started {
+            self.out.write_all(BINARY_MAGIC)?;
+            self.started = true;
+        }
+        varint::write_u64(&mut self.out, event.step)?;
+        varint::write_u64(&mut self.out, event.pc as u64)?;
+        varint::write_value(&mut self.out, event.instruction.raw)?;
+        varint::write_u64(&mut self.out, event.operands.len() as u64)?;
+        for value in &event.operands {
+            varint::write_value(&mut self.out, *value)?;
+        }
+        varint::write_u64(&mut self.out, event.reads.len() as u64)?;
+        for value in &event.reads {
+            varint::write_value(&mut self.out, *value)?;
+        }
+        match event.write {
+            Some((address, value)) => {
+                varint::write_u64(&mut self.out, address as u64 + 1)?;
+                varint::write_value(&mut self.out, value)
+            }
+            None => varint::write_u64(&mut self.out, 0),
+        }
+    }
+}
+
+impl<W: Write> Tracer for Binary<W> {
+    fn trace(&mut self, event: &Event) {
+        let _ = self.write_event(event);
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_values<R: Read>(input: &mut R) -> io::Result<Vec<Value>> {
+    let count = varint::read_u64(input)?;
+    if count > 3 {
+        return Err(invalid("too many values in event"));
+    }
+    (0..count).map(|_| varint::read_value(input)).collect()
+}
+
+pub fn read_binary<R: Read>(mut input: R) -> io::Result<Vec<Event>> {
+    let mut events = Vec::new();
+
+    let mut magic = [0u8; 4];
+    match input.read_exact(&mut magic) {
+        Ok(_) if &magic == BINARY_MAGIC => (),
+        Ok(_) => return Err(invalid("not a binary trace")),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(events),
+        Err(e) => return Err(e),
+    }
+
+    loop {
+        let step = match varint::read_u64(&mut input) {
+            Ok(step) => step,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(events),
+            Err(e) => return Err(e),
+        };
+        let pc = varint::read_u64(&mut input)? as usize;
+        let raw = varint::read_value(&mut input)?;
+        let instruction = Instruction::decode(pc, raw).map_err(|e| invalid(&e.to_string()))?;
+        let operands = read_values(&mut input)?;
+        let reads = read_values(&mut input)?;
+        let write = match varint::read_u64(&mut input)? {
+            0 => None,
+            address => Some(((address - 1) as usize, varint::read_value(&mut input)?)),
+        };
+        events.push(Event { step, pc, instruction, operands, reads, write });
+    }
+}
+
+// Aggregate statistics over a run: how often each opcode ran and which
+// addresses were executed the most.
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    steps: u64,
+    ops: BTreeMap<Value, u64>,
+    addresses: HashMap<usize, u64>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn op_counts(&self) -> &BTreeMap<Value, u64> {
+        &self.ops
+    }
+
+    // The `count` most executed addresses, most executed first.
+    pub fn hottest(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut addresses: Vec<(usize, u64)> = self.addresses.iter().map(|(a, c)| (*a, *c)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses.truncate(count);
+        addresses
+    }
+}
+
+impl Tracer for Profile {
+    fn trace(&mut self, event: &Event) {
+        self.steps += 1;
+        *self.ops.entry(event.instruction.op).or_insert(0) += 1;
+        *self.addresses.entry(event.pc).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "steps: {}", self.steps)?;
+        for (op, count) in &self.ops {
+            let mnemonic = op_info(*op).map(|info| info.mnemonic).unwrap_or("???");
+            writeln!(f, "  {:<4} {:>10}", mnemonic, count)?;
+        }
+        writeln!(f, "hottest addresses:")?;
+        for (address, count) in self.hottest(10) {
+            writeln!(f, "  {:>6} {:>10}", address, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod trace {
+        use super::super::*;
+        use super::super::super::{Program, Status};
+
+        #[derive(Default)]
+        struct Collect(Vec<Event>);
+
+        impl Tracer for Collect {
+            fn trace(&mut self, event: &Event) {
+                self.0.push(event.clone())
+            }
+        }
+
+        fn events(code: Vec<Value>, input: &[Value]) -> Vec<Event> {
+            let collect = Rc::new(RefCell::new(Collect::default()));
+            let mut p = Program::load(code);
+            for value in input {
+                p.push_input(*value);
+            }
+            p.set_tracer(collect.clone());
+            assert_eq!(Ok(Status::Halted), p.run());
+            let events = collect.borrow().0.clone();
+            events
+        }
+
+        #[test]
+        fn events_for_each_instruction() {
+            let events = events(vec![1, 0, 0, 0, 99], &[]);
+            assert_eq!(2, events.len());
+            assert_eq!(0, events[0].step);
+            assert_eq!(0, events[0].pc);
+            assert_eq!(vec![0, 0, 0], events[0].operands);
+            assert_eq!(vec![1, 1], events[0].reads);
+            assert_eq!(Some((0, 2)), events[0].write);
+            assert_eq!(1, events[1].step);
+            assert_eq!(4, events[1].pc);
+            assert_eq!(99, events[1].instruction.op);
+            assert_eq!(None, events[1].write);
+        }
+
+        #[test]
+        fn events_for_self_modifying() {
+            // the ADD writes 10 over its own first operand
+            let events = events(vec![1101, 5, 5, 1, 99], &[]);
+            assert_eq!(vec![5, 5, 1], events[0].operands);
+            assert_eq!(vec![5, 5], events[0].reads);
+            assert_eq!(Some((1, 10)), events[0].write);
+        }
+
+        #[test]
+        fn events_for_io_and_jumps() {
+            let events = events(vec![3, 11, 1005, 11, 7, 104, 1, 204, 11, 99, 0, 0], &[5]);
+            assert_eq!(Some((11, 5)), events[0].write);
+            assert_eq!(vec![5, 7], events[1].reads);
+            assert_eq!(vec![5], events[2].reads);
+            assert_eq!(7, events[2].pc);
+        }
+
+        #[test]
+        fn log() {
+            let mut p = Program::load(vec![1101, 2, 3, 5, 99, 0]);
+            let log = Rc::new(RefCell::new(Log::new(Vec::new())));
+            p.set_tracer(log.clone());
+            p.run().unwrap();
+            p.clear_tracer();
+            let log = Rc::try_unwrap(log).ok().unwrap().into_inner().into_inner();
+            let text = String::from_utf8(log).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+            assert_eq!(format!("{:>8} {:>6}: {:<28} reads(2,3) [5]=5", 0, 0, "ADD 2, 3, [5]"), lines[0]);
+            assert_eq!(format!("{:>8} {:>6}: {:<28} reads()", 1, 4, "HLT"), lines[1]);
+        }
+
+        #[test]
+        fn binary_round_trip() {
+            let events = events(vec![3, 11, 1005, 11, 7, 104, 1, 204, 11, 99, 0, 0], &[5]);
+            let mut binary = Binary::new(Vec::new());
+            for event in &events {
+                binary.trace(event);
+            }
+            let data = binary.into_inner();
+            assert_eq!(b"ICTR", &data[..4]);
+            assert_eq!(events, read_binary(data.as_slice()).unwrap());
+            assert_eq!(Vec::<Event>::new(), read_binary(&[][..]).unwrap());
+            assert!(read_binary(&b"nope"[..]).is_err());
+        }
+
+        #[test]
+        fn profile() {
+            let profile = Rc::new(RefCell::new(Profile::new()));
+            // count down from 3
+            let mut p = Program::load(vec![1001, 9, -1, 9, 1005, 9, 0, 99, 0, 3]);
+            p.set_tracer(profile.clone());
+            assert_eq!(Ok(Status::Halted), p.run());
+
+            let profile = profile.borrow();
+            assert_eq!(7, profile.steps());
+            assert_eq!(Some(&3), profile.op_counts().get(&1));
+            assert_eq!(Some(&3), profile.op_counts().get(&5));
+            assert_eq!(Some(&1), profile.op_counts().get(&99));
+            assert_eq!(vec![(0, 3), (4, 3)], profile.hottest(2));
+            assert!(profile.to_string().contains("ADD           3"));
+        }
+    }
+}
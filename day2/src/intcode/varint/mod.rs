@@ -0,0 +1,78 @@
+use std::io::{self, Read, Write};
+
+use super::Value;
+
+// LEB128 style variable length integers. Signed values are zigzag encoded
+// first so small negative numbers stay small.
+
+pub fn write_u64<W: Write>(out: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        match value {
+            0 => return out.write_all(&[byte]),
+            _ => out.write_all(&[byte | 0x80])?,
+        }
+    }
+}
+
+pub fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        input.read_exact(&mut byte)?;
+        if shift >= 64 || (shift == 63 && byte[0] & 0x7e != 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "varint too long"));
+        }
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+pub fn write_value<W: Write>(out: &mut W, value: Value) -> io::Result<()> {
+    write_u64(out, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+pub fn read_value<R: Read>(input: &mut R) -> io::Result<Value> {
+    let raw = read_u64(input)?;
+    Ok((raw >> 1) as Value ^ -((raw & 1) as Value))
+}
+
+#[cfg(test)]
+mod tests {
+    mod varint {
+        use super::super::*;
+
+        #[test]
+        fn round_trip() {
+            for value in [0, 1, -1, 63, -64, 64, 300, -300, Value::MAX, Value::MIN] {
+                let mut buf = Vec::new();
+                write_value(&mut buf, value).unwrap();
+                assert_eq!(value, read_value(&mut buf.as_slice()).unwrap());
+            }
+        }
+
+        #[test]
+        fn sizes() {
+            let mut buf = Vec::new();
+            write_value(&mut buf, -1).unwrap();
+            assert_eq!(vec![1], buf);
+            buf.clear();
+            write_u64(&mut buf, 300).unwrap();
+            assert_eq!(vec![0xac, 0x02], buf);
+            buf.clear();
+            write_u64(&mut buf, u64::MAX).unwrap();
+            assert_eq!(10, buf.len());
+        }
+
+        #[test]
+        fn truncated() {
+            assert!(read_u64(&mut [0x80u8].as_ref()).is_err());
+            assert!(read_u64(&mut [0xffu8; 11].as_ref()).is_err());
+        }
+    }
+}
@@ -1,25 +1,430 @@
-mod intcode;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-fn main() {
-    match intcode::Program::load_from_file("data/input.txt") {
-        Ok(mut p) => {
-            for noun in 0..=99 {
-                for verb in 0..=99 {
-                    match p.call(noun, verb) {
-                        Ok(output) => match output {
-                            19690720 => {
-                                println!("output: {}", 100 * noun + verb);
-                                return
-                            }
-                            _ => continue,
-                        }
-                        Err(e) => println!("error calling program: {}", e),
-                    }
+use aoc2019_2::intcode;
 
-                }
+const DEFAULT_PATH: &str = "data/input.txt";
+
+// Far more than any noun and verb need, it just stops a bad pair hanging.
+const SOLVE_STEPS: u64 = 100_000;
+
+const BENCH_ROUNDS: usize = 5;
+
+const FUZZ_ITERATIONS: u64 = 10_000;
+
+// A tight loop for the benchmark, counting down from its input.
+const BENCH_LOOP: &str = "
+            IN [n]
+    loop:   ADD [n], -1, [n]
+            MUL [n], 3, [x]
+            JT [n], loop
+            HLT
+    n:      .data 0
+    x:      .data 0
+";
+
+fn solve() {
+    match intcode::Program::load_from_file(DEFAULT_PATH) {
+        Ok(p) => {
+            let report = intcode::search::Search::new(p.memory().to_vec(), 19690720)
+                .param(1, 0..=99)
+                .param(2, 0..=99)
+                .budget(intcode::budget::Budget { steps: Some(SOLVE_STEPS), ..Default::default() })
+                .first();
+            match report.matches.first() {
+                Some(found) => println!("output: {}", 100 * found[0] + found[1]),
+                None => println!("not found"),
             }
+            println!("evaluations: {}", report.evaluations);
         }
         Err(e) => println!("couldn't load file: {}", e)
     }
-    println!("not found")
+}
+
+// Solve day 2 from the program's output expression rather than by search.
+fn symbolic(path: &str) {
+    let program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    match intcode::symbolic::evaluate(&program, &[1, 2], 0) {
+        Ok(expr) => {
+            println!("memory[0] = {}", expr);
+            match intcode::symbolic::solve(&expr, 19690720, &[(1, 0..=99), (2, 0..=99)]) {
+                Some(found) => println!("output: {}", 100 * found[0] + found[1]),
+                None => println!("not found"),
+            }
+        }
+        Err(e) => println!("error: {}", e),
+    }
+}
+
+fn disasm(path: &str) {
+    match intcode::Program::load_from_file(path) {
+        Ok(p) => print!("{}", intcode::disasm::listing(&p.memory().to_vec())),
+        Err(e) => println!("couldn't load file: {}", e),
+    }
+}
+
+// Print the control-flow graph as Graphviz DOT, with a summary of what the
+// analysis found on standard error.
+fn cfg(path: &str) {
+    let program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    let analysis = intcode::analysis::analyse(&program.memory().to_vec());
+    print!("{}", analysis.dot());
+
+    let list = |addrs: &[usize]| addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+    eprintln!("blocks: {}", analysis.blocks.len());
+    eprintln!("code words: {} of {}", analysis.code.len(), program.memory().len());
+    for (pc, addr) in &analysis.self_modifying {
+        eprintln!("self-modifying write at {} to {}", pc, addr);
+    }
+    if !analysis.unreachable.is_empty() {
+        eprintln!("unreachable instructions: {}", list(&analysis.unreachable));
+    }
+    if !analysis.invalid.is_empty() {
+        eprintln!("invalid instructions: {}", list(&analysis.invalid));
+    }
+    if !analysis.indirect.is_empty() {
+        eprintln!("indirect jumps: {}", list(&analysis.indirect));
+    }
+}
+
+fn asm(path: &str) {
+    match fs::read_to_string(path) {
+        Ok(source) => match intcode::asm::assemble(&source) {
+            Ok(assembly) => println!(
+                "{}",
+                assembly.code.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","),
+            ),
+            Err(e) => println!("couldn't assemble {}: {}", path, e),
+        }
+        Err(e) => println!("couldn't read file: {}", e),
+    }
+}
+
+// Write a program as a compressed binary image. Assembly sources (.asm) keep
+// their labels as symbols.
+fn pack(path: &str, out: Option<&String>, name: Option<&String>) {
+    let out = match out {
+        Some(out) => out,
+        None => return println!("usage: pack <program> <image> [name]"),
+    };
+    let mut image = match path.ends_with(".asm") {
+        true => match fs::read_to_string(path).map(|source| intcode::asm::assemble(&source)) {
+            Ok(Ok(assembly)) => {
+                let mut image = intcode::image::Image::new(assembly.code);
+                image.symbols = assembly
+                    .labels
+                    .into_iter()
+                    .filter(|(_, address)| *address >= 0)
+                    .map(|(label, address)| (label, address as usize))
+                    .collect();
+                image
+            }
+            Ok(Err(e)) => return println!("couldn't assemble {}: {}", path, e),
+            Err(e) => return println!("couldn't read file: {}", e),
+        }
+        false => match intcode::Program::load_from_file(path) {
+            Ok(program) => intcode::image::Image::new(program.memory().to_vec()),
+            Err(e) => return println!("couldn't load file: {}", e),
+        }
+    };
+    image.name = name.cloned().unwrap_or_default();
+    if let Err(e) = image.save(out, true) {
+        println!("couldn't write {}: {}", out, e);
+    }
+}
+
+// Print an image as program text, with its header on standard error.
+fn unpack(path: &str) {
+    let image = match intcode::image::Image::load(path) {
+        Ok(image) => image,
+        Err(e) => return println!("couldn't load image: {}", e),
+    };
+    println!("{}", image.code.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","));
+    eprintln!("name: {}", image.name);
+    eprintln!("entry: {}", image.entry);
+    eprintln!("memory: {}", image.memory_hint);
+    for (symbol, address) in &image.symbols {
+        eprintln!("{:>8} {}", address, symbol);
+    }
+}
+
+// Print a Rust module equivalent to the program. Any addresses after the path
+// are cells the caller will change before running it.
+fn compile(path: &str, dynamic: &[String]) {
+    let program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    let mut compiler = intcode::compile::Compiler::new(program.memory().to_vec());
+    for addr in dynamic {
+        match addr.parse() {
+            Ok(addr) => compiler = compiler.dynamic(addr),
+            Err(_) => return println!("invalid address: {}", addr),
+        }
+    }
+    print!("{}", compiler.generate());
+}
+
+fn debug(path: &str) {
+    let program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    let mut debugger = intcode::debug::Debugger::new(program);
+    print!("{}", debugger.exec("regs").unwrap_or_default());
+
+    let stdin = io::stdin();
+    loop {
+        print!("(debug) ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => match line.trim() {
+                "q" | "quit" => return,
+                command => match debugger.exec(command) {
+                    Ok(out) => print!("{}", out),
+                    Err(e) => println!("{}", e),
+                }
+            }
+        }
+    }
+}
+
+fn ascii(path: &str) {
+    let program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    let stdin = io::stdin();
+    match intcode::ascii::Ascii::new(program).interactive(stdin.lock(), io::stdout()) {
+        Ok(intcode::Status::Halted) => (),
+        Ok(_) => println!("\nprogram is still waiting for input"),
+        Err(e) => println!("error: {}", e),
+    }
+}
+
+type Engine = fn(&mut intcode::Program) -> Result<intcode::Status, intcode::Error>;
+
+// Time `runs` loads and runs of `code` with the given inputs on both the
+// interpreter and the pre-decoded engine.
+fn time(name: &str, runs: usize, code: &[intcode::Value], setup: &dyn Fn(&mut intcode::Program, usize)) {
+    let engines: [(&str, Engine); 2] = [
+        ("interpreter", intcode::Program::run),
+        ("fast", intcode::fast::run),
+    ];
+    // best of a few rounds to keep noise down
+    let mut times = Vec::new();
+    for (engine, run) in engines.iter() {
+        let mut best = Duration::MAX;
+        for _ in 0..BENCH_ROUNDS {
+            let start = Instant::now();
+            for idx in 0..runs {
+                let mut program = intcode::Program::load(code.to_vec());
+                setup(&mut program, idx);
+                if let Err(e) = run(&mut program) {
+                    return println!("{} failed on {}: {}", engine, name, e);
+                }
+            }
+            best = best.min(start.elapsed());
+        }
+        times.push(best);
+    }
+    let ratio = times[0].as_secs_f64() / times[1].as_secs_f64().max(Duration::from_nanos(1).as_secs_f64());
+    println!("{:<12} interpreter {:>10.2?}  fast {:>10.2?}  ({:.1}x)", name, times[0], times[1], ratio);
+}
+
+fn bench(path: &str) {
+    let program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    time("noun/verb", 10_000, &program.memory().to_vec(), &|p, idx| {
+        p.set_memory_at(1, (idx / 100) as intcode::Value);
+        p.set_memory_at(2, (idx % 100) as intcode::Value);
+    });
+
+    match intcode::asm::assemble(BENCH_LOOP) {
+        Ok(assembly) => time("loop", 1, &assembly.code, &|p, _| p.push_input(1_000_000)),
+        Err(e) => println!("couldn't assemble benchmark: {}", e),
+    }
+}
+
+// Run a program with standard input and output, logging every instruction to
+// standard error and finishing with a profile. With an output path a binary
+// trace is written there instead of the log.
+fn trace(path: &str, out: Option<&String>) {
+    let mut program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    program.set_input(intcode::io::Stdin::new());
+    program.set_output(intcode::io::Stdout);
+
+    let profile = Rc::new(RefCell::new(intcode::trace::Profile::new()));
+    let result = match out {
+        Some(out) => match fs::File::create(out) {
+            Ok(file) => {
+                let binary = intcode::trace::Binary::new(io::BufWriter::new(file));
+                program.set_tracer(Tee(binary, profile.clone()));
+                program.run()
+            }
+            Err(e) => return println!("couldn't create {}: {}", out, e),
+        }
+        None => {
+            program.set_tracer(Tee(intcode::trace::Log::new(io::stderr()), profile.clone()));
+            program.run()
+        }
+    };
+    // drop the tracer so a binary trace gets flushed
+    program.clear_tracer();
+
+    match result {
+        Ok(status) => eprintln!("{:?}", status),
+        Err(e) => eprintln!("error: {}", e),
+    }
+    eprint!("{}", profile.borrow());
+}
+
+// Run a program with standard input and output, recording what it reads and
+// writes to a session file that `replay` can check a later run against.
+fn record(path: &str, out: Option<&String>) {
+    let out = match out {
+        Some(out) => out,
+        None => return println!("usage: record <program> <session>"),
+    };
+    let mut program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    let file = match fs::File::create(out) {
+        Ok(file) => file,
+        Err(e) => return println!("couldn't create {}: {}", out, e),
+    };
+    program.set_input(intcode::io::Stdin::new());
+    program.set_output(intcode::io::Stdout);
+    program.set_tracer(intcode::replay::Recorder::new(io::BufWriter::new(file)));
+    let result = program.run();
+    // drop the recorder so the session gets flushed
+    program.clear_tracer();
+    match result {
+        Ok(status) => eprintln!("{:?}", status),
+        Err(e) => eprintln!("error: {}", e),
+    }
+}
+
+// Replay a recorded session against a program, then show what the program
+// does once the recording runs out.
+fn replay(path: &str, session: Option<&String>) {
+    let session = match session {
+        Some(session) => session,
+        None => return println!("usage: replay <program> <session>"),
+    };
+    let mut program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    let session = match fs::File::open(session).and_then(|f| intcode::replay::Session::read(io::BufReader::new(f))) {
+        Ok(session) => session,
+        Err(e) => return println!("couldn't read {}: {}", session, e),
+    };
+    match intcode::replay::replay(&mut program, &session) {
+        Ok(()) => println!("replayed {} entries", session.entries.len()),
+        Err(divergence) => return println!("diverged at {}", divergence),
+    }
+    match program.run() {
+        Ok(status) => println!("then: {:?}", status),
+        Err(e) => println!("then: {}", e),
+    }
+}
+
+// Parse a cell to fuzz, given as `address=low..high`.
+fn parse_cell(arg: &str) -> Option<(usize, RangeInclusive<intcode::Value>)> {
+    let (address, range) = arg.split_once('=')?;
+    let (low, high) = range.split_once("..")?;
+    Some((address.parse().ok()?, low.parse().ok()?..=high.parse().ok()?))
+}
+
+// Fuzz a program's input, and any cells given after the iteration count and
+// seed, printing a minimized reproducer for each crash found.
+fn fuzz(path: &str, args: &[String]) {
+    let program = match intcode::Program::load_from_file(path) {
+        Ok(program) => program,
+        Err(e) => return println!("couldn't load file: {}", e),
+    };
+    let iterations = match args.first().map(|n| n.parse()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return println!("invalid iteration count: {}", args[0]),
+        None => FUZZ_ITERATIONS,
+    };
+    let seed = match args.get(1).map(|n| n.parse()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return println!("invalid seed: {}", args[1]),
+        None => 1,
+    };
+    let mut fuzzer = intcode::fuzz::Fuzzer::new(program.memory().to_vec()).seed(seed);
+    for arg in args.get(2..).unwrap_or(&[]) {
+        match parse_cell(arg) {
+            Some((address, range)) => fuzzer = fuzzer.cell(address, range),
+            None => return println!("invalid cell: {} (expected address=low..high)", arg),
+        }
+    }
+
+    let report = fuzzer.run(iterations);
+    println!("executions: {}", report.executions);
+    println!("corpus: {}", report.corpus.len());
+    println!("coverage: {} addresses", report.coverage.len());
+    let join = |values: &[intcode::Value]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",");
+    for crash in &report.crashes {
+        println!("crash: {}", crash.error);
+        println!("  input: {}", join(&crash.case.input));
+        if !crash.case.cells.is_empty() {
+            println!("  cells: {}", join(&crash.case.cells));
+        }
+    }
+}
+
+// Sends trace events to two tracers.
+struct Tee<A, B>(A, B);
+
+impl<A: intcode::trace::Tracer, B: intcode::trace::Tracer> intcode::trace::Tracer for Tee<A, B> {
+    fn trace(&mut self, event: &intcode::trace::Event) {
+        self.0.trace(event);
+        self.1.trace(event);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = args.get(1).map(|p| p.as_str()).unwrap_or(DEFAULT_PATH);
+    match args.first().map(|a| a.as_str()) {
+        Some("disasm") => disasm(path),
+        Some("asm") => asm(path),
+        Some("bench") => bench(path),
+        Some("cfg") => cfg(path),
+        Some("compile") => compile(path, args.get(2..).unwrap_or(&[])),
+        Some("debug") => debug(path),
+        Some("pack") => pack(path, args.get(2), args.get(3)),
+        Some("unpack") => unpack(path),
+        Some("fuzz") => fuzz(path, args.get(2..).unwrap_or(&[])),
+        Some("ascii") => ascii(path),
+        Some("trace") => trace(path, args.get(2)),
+        Some("record") => record(path, args.get(2)),
+        Some("replay") => replay(path, args.get(2)),
+        Some("symbolic") => symbolic(path),
+        Some(cmd) => println!("unknown command: {}", cmd),
+        None => solve(),
+    }
 }